@@ -0,0 +1,240 @@
+//! Advisories that block (or warn about) known-bad dllpacks and libraries at resolve time.
+//!
+//! An advisory database is a JSON file like:
+//! ```json
+//! {
+//!   "advisories": [
+//!     {
+//!       "id": "ADDER-2026-001",
+//!       "reason": "crashes on startup",
+//!       "severity": "deny",
+//!       "package": { "name": "adder", "versions": ">=1.2.0, <1.2.3" }
+//!     },
+//!     {
+//!       "id": "ADDER-2026-002",
+//!       "reason": "compromised build",
+//!       "severity": "warn",
+//!       "url": "https://example.com/adder/*/libadder.so"
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! An advisory matches when all of its conditions match:
+//! - `url`: a pattern of manifest or library URLs, where `*` matches any sequence of characters.
+//! - `package`: a package name of a manifest's metadata, optionally with a semver range of versions.
+//! - `sha256`: the content hash of a library.
+//!
+//! The database is configured for the whole process with [`set_advisory_db`].
+
+use crate::dllpack_file::Metadata;
+use crate::glob::glob_match;
+use crate::resolve::ResolveError;
+use anyhow::{anyhow, Result};
+use log::warn;
+use semver::VersionReq;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::{Arc, LazyLock, Mutex};
+use url::Url;
+
+/// What happens when an advisory matches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Log a warning and continue.
+    Warn,
+    /// Refuse to resolve the dllpack.
+    Deny,
+}
+
+/// Matches packages by the metadata of their manifests.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageMatcher {
+    pub name: String,
+    /// The affected versions. All versions are affected if not provided.
+    #[serde(default)]
+    pub versions: Option<VersionReq>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advisory {
+    pub id: String,
+    pub reason: String,
+    pub severity: Severity,
+    #[serde(default)]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub package: Option<PackageMatcher>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+}
+
+impl Advisory {
+    fn url_matches(&self, url: &Url) -> bool {
+        self.url
+            .as_ref()
+            .is_none_or(|pattern| glob_match(pattern, url.as_str()))
+    }
+
+    fn matches_manifest(&self, url: &Url, metadata: &Metadata) -> bool {
+        if self.sha256.is_some() || (self.url.is_none() && self.package.is_none()) {
+            return false;
+        }
+
+        let package_matches = match &self.package {
+            None => true,
+            Some(package) => {
+                metadata.name.as_deref() == Some(package.name.as_str())
+                    && match (&package.versions, &metadata.version) {
+                        (None, _) => true,
+                        (Some(range), Some(version)) => range.matches(version),
+                        (Some(_), None) => false,
+                    }
+            }
+        };
+
+        package_matches && self.url_matches(url)
+    }
+
+    fn matches_lib(&self, url: &Url, sha256: Option<&str>) -> bool {
+        if self.package.is_some() || (self.url.is_none() && self.sha256.is_none()) {
+            return false;
+        }
+
+        let hash_matches = match &self.sha256 {
+            None => true,
+            Some(expected) => sha256.is_some_and(|h| h.eq_ignore_ascii_case(expected)),
+        };
+
+        hash_matches && self.url_matches(url)
+    }
+
+    /// Warns about or refuses `url`, which this advisory matches.
+    fn apply(&self, url: &Url, path: &[Url]) -> Result<()> {
+        let path: Vec<_> = path.iter().map(Url::as_str).collect();
+        let message = format!(
+            "advisory {} matches {}: {}\n  dependency path: {}",
+            self.id,
+            url,
+            self.reason,
+            path.join(" -> ")
+        );
+
+        match self.severity {
+            Severity::Warn => {
+                warn!("{}", message);
+                Ok(())
+            }
+            Severity::Deny => Err(anyhow!(ResolveError::Advisory(message))),
+        }
+    }
+}
+
+/// A set of advisories.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdvisoryDb {
+    pub advisories: Vec<Advisory>,
+}
+
+impl AdvisoryDb {
+    pub fn from_str(s: &str) -> Result<Self> {
+        serde_json::from_str(s).map_err(Into::into)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let s = std::fs::read_to_string(path)?;
+        Self::from_str(&s)
+    }
+
+    /// Whether any advisory matches libraries by their content hash.
+    pub(crate) fn has_hash_advisories(&self) -> bool {
+        self.advisories.iter().any(|a| a.sha256.is_some())
+    }
+
+    /// Checks a manifest, reached through the dllpacks in `path` (which ends with it).
+    pub(crate) fn check_manifest(
+        &self,
+        url: &Url,
+        metadata: &Metadata,
+        path: &[Url],
+    ) -> Result<()> {
+        for advisory in &self.advisories {
+            if advisory.matches_manifest(url, metadata) {
+                advisory.apply(url, path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a library, declared by the last dllpack in `path`.
+    pub(crate) fn check_lib(&self, url: &Url, sha256: Option<&str>, path: &[Url]) -> Result<()> {
+        for advisory in &self.advisories {
+            if advisory.matches_lib(url, sha256) {
+                let mut path = path.to_vec();
+                path.push(url.clone());
+                advisory.apply(url, &path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+static ADVISORY_DB: LazyLock<Mutex<Option<Arc<AdvisoryDb>>>> = LazyLock::new(|| Mutex::new(None));
+
+/// Sets the advisory database consulted by every resolution in this process,
+/// or removes it with `None`.
+pub fn set_advisory_db(db: Option<AdvisoryDb>) {
+    *ADVISORY_DB.lock().unwrap() = db.map(Arc::new);
+}
+
+/// The advisory database currently in effect.
+pub(crate) fn advisory_db() -> Option<Arc<AdvisoryDb>> {
+    ADVISORY_DB.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_check_manifest() {
+        let db = AdvisoryDb::from_str(
+            r#"{
+                "advisories": [{
+                    "id": "ADDER-1",
+                    "reason": "crashes on startup",
+                    "severity": "deny",
+                    "package": { "name": "adder", "versions": ">=1.2.0, <1.2.3" }
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let root = Url::from_str("https://example.com/app.dllpack").unwrap();
+        let adder = Url::from_str("https://example.com/adder.dllpack").unwrap();
+        let metadata = |version: &str| Metadata {
+            name: Some("adder".to_string()),
+            version: Some(semver::Version::parse(version).unwrap()),
+            ..Default::default()
+        };
+        let path = [root, adder.clone()];
+
+        assert!(db.check_manifest(&adder, &metadata("1.2.3"), &path).is_ok());
+
+        let err = db
+            .check_manifest(&adder, &metadata("1.2.1"), &path)
+            .unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ResolveError::Advisory(_))
+        ));
+        let message = err.to_string();
+        assert!(message.contains("ADDER-1"));
+        assert!(message
+            .contains("https://example.com/app.dllpack -> https://example.com/adder.dllpack"));
+    }
+}
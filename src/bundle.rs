@@ -0,0 +1,237 @@
+//! Single-file bundles of a dllpack.
+//!
+//! A bundle is an uncompressed tar archive with the layout written by [`vendor`](crate::vendor::vendor),
+//! with the root manifest at [`BUNDLE_MANIFEST`]. All URLs in its manifests are relative paths
+//! into the archive, so it holds the artifacts for every platform and all nested dependency packs.
+//!
+//! A bundle can be used anywhere a `.dllpack` URL is accepted, including `file://` URLs of local files.
+//! It is unpacked into the cache the first time it is read.
+
+use crate::cache::{self, CacheLocation};
+use crate::dllpack_file::DllPackFile;
+use crate::vendor::vendor_with_root_name;
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use std::fs;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The path of the root manifest inside a bundle.
+pub const BUNDLE_MANIFEST: &str = "manifest.dllpack";
+
+/// The size of the header of a tar archive, which holds the magic of the format.
+const TAR_HEADER_SIZE: u64 = 512;
+
+/// Returns whether `content`, or its first [`TAR_HEADER_SIZE`] bytes,
+/// is a bundle (a tar archive) rather than a plain manifest.
+pub(crate) fn is_bundle(content: &[u8]) -> bool {
+    content.len() > 262 && &content[257..262] == b"ustar"
+}
+
+/// Returns whether the file at `path` is a bundle, reading only its header.
+pub(crate) fn is_bundle_file(path: &Path) -> Result<bool> {
+    let mut header = Vec::new();
+    fs::File::open(path)?
+        .take(TAR_HEADER_SIZE)
+        .read_to_end(&mut header)?;
+
+    Ok(is_bundle(&header))
+}
+
+/// Lists all files under `dir` as paths relative to it, sorted.
+fn list_files(dir: &Path, prefix: &Path, result: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let relative = prefix.join(entry.file_name());
+
+        if entry.file_type()?.is_dir() {
+            list_files(&entry.path(), &relative, result)?;
+        } else {
+            result.push(relative);
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the contents of `dir` as a tar archive to `out_file`.
+///
+/// The archive is deterministic: entries are sorted by path,
+/// and timestamps, owners and permissions are fixed.
+fn write_tar(dir: &Path, out_file: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    list_files(dir, Path::new(""), &mut files)?;
+    files.sort();
+
+    let mut builder = tar::Builder::new(Vec::new());
+
+    for file in files {
+        let content = fs::read(dir.join(&file))?;
+
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_entry_type(tar::EntryType::Regular);
+
+        // Entry paths always use `/`, regardless of the host.
+        let entry_path = file
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        builder.append_data(&mut header, entry_path, content.as_slice())?;
+    }
+
+    cache::write_atomic(out_file, &builder.into_inner()?)
+}
+
+/// Packs the dllpack at `url`, together with all of its dependencies for **all platforms**,
+/// into a single bundle file at `out_file`.
+/// Everything is downloaded through the cache at `work_dir`.
+pub fn bundle(url: &Url, work_dir: &impl CacheLocation, out_file: &PathBuf) -> Result<()> {
+    // Every call stages into its own directory, so concurrent calls do not clobber each other.
+    let staging_dir = cache::temp_path(&std::env::temp_dir(), "dll-pack-bundle");
+
+    let result = vendor_with_root_name(url, work_dir, &staging_dir, BUNDLE_MANIFEST)
+        .and_then(|_| write_tar(&staging_dir, out_file));
+
+    if let Err(e) = fs::remove_dir_all(&staging_dir) {
+        warn!(
+            "failed to remove the staging directory {}: {}",
+            staging_dir.display(),
+            e
+        );
+    }
+
+    result
+}
+
+/// Unpacks a bundle (if not done yet) and reads its root manifest.
+/// Returns the manifest and the directory the bundle is unpacked into.
+///
+/// Bundles are unpacked into a directory named after the hash of their content,
+/// so a changed bundle at the same URL never reuses stale files.
+/// The directory is looked up in `lookup_dirs` in order, and the bundle is unpacked
+/// into `bundles_dir`, a directory of the writable cache layer, if it is in none of them.
+pub(crate) fn read_bundle(
+    content: &[u8],
+    lookup_dirs: &[PathBuf],
+    bundles_dir: Option<&Path>,
+) -> Result<(DllPackFile, PathBuf)> {
+    let hash = cache::sha256_hex(content);
+
+    let dir = match lookup_dirs
+        .iter()
+        .map(|d| d.join(&hash))
+        .find(|d| d.exists())
+    {
+        Some(dir) => dir,
+        None => {
+            let bundles_dir = bundles_dir.ok_or(anyhow!(
+                "The bundle is not unpacked and no writable cache layer is available"
+            ))?;
+            let dir = bundles_dir.join(&hash);
+            unpack(content, bundles_dir, &dir)?;
+            dir
+        }
+    };
+
+    // The base URL must be absolute, even if the cache directory is given as a relative path.
+    let manifest_path = fs::canonicalize(dir.join(BUNDLE_MANIFEST))?;
+    let base = Url::from_file_path(&manifest_path)
+        .map_err(|_| anyhow!("Invalid bundle path: {}", manifest_path.display()))?;
+
+    Ok((
+        DllPackFile::from_file_with_base(&manifest_path, &base)?,
+        dir,
+    ))
+}
+
+/// Reads the root manifest of a bundle without unpacking it.
+/// Its URLs are based on the directory the bundle would be unpacked into in `work_dir`.
+pub(crate) fn read_bundle_manifest(content: &[u8], work_dir: &Path) -> Result<DllPackFile> {
+    let manifest_path = fs::canonicalize(work_dir)?
+        .join(cache::BUNDLES_DIR)
+        .join(cache::sha256_hex(content))
+        .join(BUNDLE_MANIFEST);
+    let base = Url::from_file_path(&manifest_path)
+        .map_err(|_| anyhow!("Invalid bundle path: {}", manifest_path.display()))?;
+
+    let mut archive = tar::Archive::new(Cursor::new(content));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()? == Path::new(BUNDLE_MANIFEST) {
+            let mut manifest = String::new();
+            entry.read_to_string(&mut manifest)?;
+            return DllPackFile::from_str_with_base(&manifest, &base);
+        }
+    }
+
+    Err(anyhow!("The bundle has no {}", BUNDLE_MANIFEST))
+}
+
+/// Unpacks a bundle into `dir`, in `bundles_dir`.
+fn unpack(content: &[u8], bundles_dir: &Path, dir: &Path) -> Result<()> {
+    debug!("unpacking bundle: {}", dir.display());
+
+    let tmp_dir = cache::temp_path(bundles_dir, "unpack");
+    fs::create_dir_all(&tmp_dir)?;
+
+    let mut archive = tar::Archive::new(Cursor::new(content));
+    for entry in archive.entries()? {
+        // `unpack_in` refuses entries that would be written outside of `tmp_dir`.
+        entry?.unpack_in(&tmp_dir)?;
+    }
+
+    if let Err(e) = fs::rename(&tmp_dir, dir) {
+        // Another process may have unpacked the same bundle in the meantime.
+        fs::remove_dir_all(&tmp_dir)?;
+        if !dir.exists() {
+            return Err(e.into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::CacheLayers;
+    use crate::resolve::resolve;
+    use crate::test_fixtures::{test_dir, write_app_and_dep, PLATFORM};
+
+    #[test]
+    fn test_bundle_round_trip() {
+        let dir = test_dir("bundle-test");
+        let src_dir = dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+        let url = write_app_and_dep(&src_dir, "app");
+
+        let bundle_path = dir.join("app.bundle.dllpack");
+        bundle(&url, &dir.join("work"), &bundle_path).unwrap();
+        assert!(is_bundle_file(&bundle_path).unwrap());
+
+        // The bundle is read without its sources.
+        fs::remove_dir_all(&src_dir).unwrap();
+        let bundle_url = Url::from_file_path(&bundle_path).unwrap();
+        let read_only = dir.join("read-only");
+        let (base, deps) = resolve(&bundle_url, &read_only, PLATFORM).unwrap();
+        assert_eq!(fs::read_to_string(&base.path).unwrap(), "app");
+        assert_eq!(fs::read_to_string(&deps[0].path).unwrap(), "dep");
+
+        // A bundle that is cached in a read-only layer is unpacked into the writable one.
+        fs::remove_dir_all(read_only.join(cache::BUNDLES_DIR)).unwrap();
+        let writable = dir.join("writable");
+        let layers = CacheLayers::new().read_only(&read_only).writable(&writable);
+        resolve(&bundle_url, &layers, PLATFORM).unwrap();
+        assert!(!read_only.join(cache::BUNDLES_DIR).exists());
+        assert!(writable.join(cache::BUNDLES_DIR).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
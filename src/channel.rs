@@ -0,0 +1,245 @@
+//! Release channels: small documents that point to a concrete dllpack.
+//!
+//! A channel is published at a stable URL, while every release keeps its own immutable URL:
+//! ```json
+//! {
+//!   "spec-version": "1.0.0",
+//!   "channel": { "name": "latest", "target": "adder-1.3.0.dllpack" }
+//! }
+//! ```
+//! The target may be relative to the channel URL, and may itself be another channel.
+//!
+//! Anywhere a `.dllpack` URL is resolved, channels are followed to the concrete URL,
+//! which is then used as the identity of the dllpack in the cache.
+//! The target of a channel is revalidated with the server on every resolution
+//! (using `ETag` and `Last-Modified`), and the last known target is used if the server is unreachable.
+
+use crate::cache::{self, CacheLayers, CacheLocation};
+use crate::download::{
+    fetch, fetch_revalidate, write_manifest, ManifestInfo, Revalidated, Validators,
+};
+use crate::fetch_policy::join_reference;
+use crate::update::{record_origin, Origin};
+use anyhow::{anyhow, Result};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The maximum number of channels followed in a chain before giving up.
+pub const MAX_CHANNEL_DEPTH: usize = 8;
+
+/// The body of a channel document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Channel {
+    /// Optional name of the channel, such as `latest` or `beta`.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The URL of the dllpack (or channel) the channel currently points to.
+    /// It may be relative to the URL of the channel.
+    pub target: String,
+}
+
+/// A struct corresponding to the top level of a channel document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChannelFile {
+    /// The version of the specification, as in dllpack files.
+    /// Currently only "1.0.0" is allowed.
+    #[serde(rename = "spec-version")]
+    pub spec_version: String,
+
+    pub channel: Channel,
+}
+
+impl ChannelFile {
+    /// Parses `content` as a channel document fetched from `base`, and returns its absolute target.
+    /// Returns `None` if `content` is not a channel document (for example, a dllpack file or a bundle).
+    pub fn target_of(content: &[u8], base: &Url) -> Result<Option<Url>> {
+        let Ok(value) = serde_json::from_slice::<Value>(content) else {
+            return Ok(None);
+        };
+        if value.get("channel").is_none() {
+            return Ok(None);
+        }
+
+        let file: ChannelFile = serde_json::from_value(value)?;
+        if file.spec_version != "1.0.0" {
+            return Err(anyhow!("Unsupported spec version: {}", file.spec_version));
+        }
+
+        Ok(Some(join_reference(base, &file.channel.target)?))
+    }
+}
+
+/// The last known target of a channel, stored in the cache.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedChannel {
+    #[serde(with = "url_serde")]
+    target: Url,
+    #[serde(flatten)]
+    validators: Validators,
+}
+
+fn channel_path(work_dir: &Path, url: &Url) -> PathBuf {
+    work_dir.join(cache::CHANNELS_DIR).join(cache::url_key(url))
+}
+
+fn read_cached_channel(url: &Url, layers: &CacheLayers) -> Result<Option<CachedChannel>> {
+    for dir in layers.lookup_dirs() {
+        let path = channel_path(dir, url);
+        if path.exists() {
+            return Ok(Some(serde_json::from_slice(&fs::read(path)?)?));
+        }
+    }
+
+    Ok(None)
+}
+
+fn write_cached_channel(url: &Url, layers: &CacheLayers, cached: &CachedChannel) -> Result<()> {
+    let Some(dir) = layers.writable_dir() else {
+        debug!("no writable cache layer, not caching channel {}", url);
+        return Ok(());
+    };
+
+    cache::write_atomic(&channel_path(dir, url), &serde_json::to_vec(cached)?)?;
+    cache::record_url(&cache::index_path(dir, &cache::url_key(url)), url)
+}
+
+/// Resolves one step: returns the target if `url` is a channel, or `None` if it is a dllpack.
+fn follow_once(url: &Url, layers: &CacheLayers) -> Result<Option<Url>> {
+    // Dllpacks are immutable, so one that is already cached needs no request.
+    let manifest_info = ManifestInfo::from_layers(url, layers)?;
+    if manifest_info.path.exists() {
+        return Ok(None);
+    }
+
+    let cached = read_cached_channel(url, layers)?;
+    let validators = cached
+        .as_ref()
+        .map(|c| c.validators.clone())
+        .unwrap_or_default();
+
+    match fetch_revalidate(url, &validators) {
+        Ok(Revalidated::NotModified) => {
+            let cached = cached.ok_or(anyhow!("Unexpected 304 Not Modified for {}", url))?;
+            debug!("channel not modified: {} -> {}", url, cached.target);
+
+            Ok(Some(cached.target))
+        }
+        Ok(Revalidated::Modified(content, validators)) => {
+            match ChannelFile::target_of(&content, url)? {
+                Some(target) => {
+                    debug!("channel: {} -> {}", url, target);
+                    write_cached_channel(
+                        url,
+                        layers,
+                        &CachedChannel {
+                            target: target.clone(),
+                            validators,
+                        },
+                    )?;
+
+                    Ok(Some(target))
+                }
+                None => {
+                    // Not a channel; keep the fetched dllpack so it is not downloaded again.
+                    write_manifest(&manifest_info, &content)?;
+
+                    Ok(None)
+                }
+            }
+        }
+        Err(e) => match cached {
+            Some(cached) => {
+                warn!(
+                    "could not revalidate channel {} ({}), using the last known target {}",
+                    url, e, cached.target
+                );
+
+                Ok(Some(cached.target))
+            }
+            None => Err(e),
+        },
+    }
+}
+
+/// Implementation of `resolve_channel` on prepared cache layers.
+pub(crate) fn follow_channels(url: &Url, layers: &CacheLayers) -> Result<Url> {
+    let mut current = url.clone();
+
+    for _ in 0..=MAX_CHANNEL_DEPTH {
+        match follow_once(&current, layers)? {
+            Some(target) => current = target,
+            None => return Ok(current),
+        }
+    }
+
+    Err(anyhow!(
+        "Too many channels chained from {} (more than {})",
+        url,
+        MAX_CHANNEL_DEPTH
+    ))
+}
+
+/// Follows the channels starting from `url` and returns the concrete dllpack URL.
+/// If `url` is not a channel, it is returned as is.
+pub fn resolve_channel(url: &Url, work_dir: &impl CacheLocation) -> Result<Url> {
+    let layers = work_dir.cache_layers().prepare()?;
+
+    let concrete_url = follow_channels(url, &layers)?;
+    if &concrete_url != url {
+        record_origin(
+            &concrete_url,
+            &Origin::Channel { url: url.clone() },
+            &layers,
+        )?;
+    }
+
+    Ok(concrete_url)
+}
+
+/// Follows the channels starting from `url` bypassing the cache,
+/// and returns the concrete dllpack URL together with the fetched dllpack.
+pub(crate) fn fetch_following_channels(url: &Url) -> Result<(Url, Vec<u8>)> {
+    let mut current = url.clone();
+
+    for _ in 0..=MAX_CHANNEL_DEPTH {
+        let content = fetch(&current)?;
+        match ChannelFile::target_of(&content, &current)? {
+            Some(target) => current = target,
+            None => return Ok((current, content)),
+        }
+    }
+
+    Err(anyhow!(
+        "Too many channels chained from {} (more than {})",
+        url,
+        MAX_CHANNEL_DEPTH
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_target_of() {
+        let base = Url::from_str("https://example.com/adder/latest.dllpack").unwrap();
+
+        let channel = br#"{
+            "spec-version": "1.0.0",
+            "channel": { "name": "latest", "target": "1.3.0/adder.dllpack" }
+        }"#;
+        assert_eq!(
+            ChannelFile::target_of(channel, &base).unwrap(),
+            Some(Url::from_str("https://example.com/adder/1.3.0/adder.dllpack").unwrap())
+        );
+
+        let dllpack = br#"{ "spec-version": "1.0.0", "manifest": { "platforms": {} } }"#;
+        assert_eq!(ChannelFile::target_of(dllpack, &base).unwrap(), None);
+    }
+}
@@ -0,0 +1,278 @@
+//! WebAssembly components, loaded for the `wasm32-wasip2` platform.
+//!
+//! Components are linked with WASI preview2, and their exported functions are called
+//! with dynamic [`Val`]s, which can be strings, lists, records and the other types
+//! of the component model:
+//! ```no_run
+//! # use dll_pack::component::Val;
+//! # fn f(lib: &mut dll_pack::Library) -> anyhow::Result<()> {
+//! let greet = lib.get_component_function("example:greet/api#greet")?;
+//! let results = greet.try_call(lib, &[Val::String("world".to_string())])?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::dllpack_file::Metadata;
+use crate::load::{CallError, CallOutput, Library, WasmRuntime, WasmState};
+use anyhow::{anyhow, Result};
+use std::time::Duration;
+use url::Url;
+use wasmtime::component::{Func, Instance};
+use wasmtime::Store;
+
+pub use wasmtime::component::{Type, Val};
+
+/// A component instance, with the same runtime state as a [`WasmLibrary`](crate::load::WasmLibrary).
+pub struct ComponentLibrary {
+    pub instance: Instance,
+    pub runtime: WasmRuntime,
+    /// See [`Library::manifest_url`].
+    pub manifest_url: Url,
+    /// See [`Library::metadata`].
+    pub metadata: Option<Metadata>,
+}
+
+impl ComponentLibrary {
+    pub(crate) fn new(
+        instance: Instance,
+        store: Store<WasmState>,
+        manifest_url: Url,
+        metadata: Option<Metadata>,
+    ) -> Self {
+        Self {
+            instance,
+            runtime: WasmRuntime::new(store),
+            manifest_url,
+            metadata,
+        }
+    }
+}
+
+/// A function exported by a component, called with dynamic values.
+pub struct ComponentFunction {
+    func: Func,
+    params: Vec<(String, Type)>,
+    results: Vec<Type>,
+}
+
+impl ComponentFunction {
+    /// The names and types of the parameters.
+    pub fn params(&self) -> &[(String, Type)] {
+        &self.params
+    }
+
+    /// The types of the results.
+    pub fn results(&self) -> &[Type] {
+        &self.results
+    }
+
+    /// Calls the function of the component `library` with `args`, and returns its results.
+    ///
+    /// The arguments must match [`Self::params`]; otherwise the call fails with
+    /// [`CallError::Other`]. Timeouts and fuel apply like to wasm functions
+    /// (see [`Function::try_call`](crate::load::Function::try_call)).
+    ///
+    /// Unlike a wasm library, a component cannot be called again after any failed call,
+    /// such as a trap, so the library is poisoned (see [`Library::is_poisoned`]).
+    pub fn try_call(&self, library: &mut Library, args: &[Val]) -> Result<Vec<Val>, CallError> {
+        self.try_call_inner(library, args, None)
+    }
+
+    /// Like [`try_call`](Self::try_call), but the call is interrupted after `timeout`
+    /// instead of the timeout of the library.
+    pub fn try_call_with_timeout(
+        &self,
+        library: &mut Library,
+        args: &[Val],
+        timeout: Duration,
+    ) -> Result<Vec<Val>, CallError> {
+        self.try_call_inner(library, args, Some(timeout))
+    }
+
+    /// The component counterpart of [`Function::try_call_captured`](crate::load::Function::try_call_captured).
+    pub fn try_call_captured(&self, library: &mut Library, args: &[Val]) -> CallOutput<Vec<Val>> {
+        let result = self.try_call_inner(library, args, None);
+        let (stdout, stderr) = library.take_captured_output();
+
+        CallOutput {
+            result,
+            stdout,
+            stderr,
+        }
+    }
+
+    /// Like [`try_call`](Self::try_call), but panics on errors.
+    pub fn call(&self, library: &mut Library, args: &[Val]) -> Vec<Val> {
+        match self.try_call(library, args) {
+            Ok(res) => res,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    fn try_call_inner(
+        &self,
+        library: &mut Library,
+        args: &[Val],
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Val>, CallError> {
+        let Library::ComponentLibrary(lib) = library else {
+            return Err(CallError::LibraryMismatch);
+        };
+        if args.len() != self.params.len() {
+            return Err(CallError::Other(anyhow!(
+                "Expected {} arguments, got {}",
+                self.params.len(),
+                args.len()
+            )));
+        }
+
+        let result = lib.runtime.run(timeout, |store| {
+            // The values are only placeholders, overwritten by the call.
+            let mut results = vec![Val::Bool(false); self.results.len()];
+            self.func.call(&mut *store, args, &mut results)?;
+            self.func.post_return(&mut *store)?;
+            Ok(results)
+        });
+
+        // Wasmtime refuses to enter a component again after a failed call
+        // (`Trap::CannotEnterComponent`), including a failed `post_return`.
+        if matches!(&result, Err(e) if !matches!(e, CallError::Poisoned)) {
+            lib.runtime.poisoned = true;
+        }
+
+        result
+    }
+}
+
+/// Splits the name of an exported function into the interface that exports it, if any,
+/// and the name of the function, such as `example:greet/api#greet`.
+fn split_export_name(name: &str) -> (Option<&str>, &str) {
+    match name.rsplit_once('#') {
+        Some((interface, func)) => (Some(interface), func),
+        None => (None, name),
+    }
+}
+
+impl Library {
+    /// Retrieves a function exported by a component, either at the top level (`name`)
+    /// or from an exported interface (`interface#name`).
+    pub fn get_component_function(&mut self, name: &str) -> Result<ComponentFunction> {
+        let Library::ComponentLibrary(ComponentLibrary {
+            instance, runtime, ..
+        }) = self
+        else {
+            return Err(anyhow!("The library is not a component"));
+        };
+        let store = &mut runtime.store;
+
+        let (interface, func_name) = split_export_name(name);
+        let interface = match interface {
+            Some(interface) => Some(
+                instance
+                    .get_export(&mut *store, None, interface)
+                    .ok_or_else(|| anyhow!("The component does not export `{}`", interface))?,
+            ),
+            None => None,
+        };
+        let index = instance
+            .get_export(&mut *store, interface.as_ref(), func_name)
+            .ok_or_else(|| anyhow!("The component does not export `{}`", name))?;
+        let func = instance
+            .get_func(&mut *store, index)
+            .ok_or_else(|| anyhow!("`{}` is not a function", name))?;
+
+        Ok(ComponentFunction {
+            func,
+            params: func.params(&*store).into_vec(),
+            results: func.results(&*store).into_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::tests::load_test_wasm;
+    use crate::load::WasmOptions;
+    use crate::wasi_config::{OutputStream, WasiConfig};
+    use wasmtime::Trap;
+
+    /// A component without imports, which exports:
+    /// - `add: func(a: u32, b: u32) -> u32`
+    /// - `echo: func(s: string) -> string`
+    /// - `swap: func(p: point) -> point`, which swaps the fields of `record point { x: u32, y: u32 }`
+    /// - `count: func(l: list<u32>) -> u32`, which returns the length of the list
+    /// - `fail: func()`, which traps
+    const COMPONENT_WASM: &[u8] = include_bytes!("testdata/component.wasm");
+
+    #[test]
+    fn test_split_export_name() {
+        assert_eq!(
+            split_export_name("example:greet/api#greet"),
+            (Some("example:greet/api"), "greet")
+        );
+        assert_eq!(split_export_name("greet"), (None, "greet"));
+    }
+
+    #[test]
+    fn test_component_calls() {
+        let options = WasmOptions {
+            wasi: WasiConfig::new()
+                .no_preopens()
+                .stdout(OutputStream::Capture),
+            ..Default::default()
+        };
+        let mut library = load_test_wasm("component", COMPONENT_WASM, "wasm32-wasip2", &options);
+
+        let add = library.get_component_function("add").unwrap();
+        assert_eq!(
+            add.call(&mut library, &[Val::U32(1), Val::U32(2)]),
+            vec![Val::U32(3)]
+        );
+
+        let echo = library.get_component_function("echo").unwrap();
+        let s = Val::String("héllo, world".to_string());
+        assert_eq!(echo.call(&mut library, std::slice::from_ref(&s)), vec![s]);
+
+        let point = |x, y| {
+            Val::Record(vec![
+                ("x".to_string(), Val::U32(x)),
+                ("y".to_string(), Val::U32(y)),
+            ])
+        };
+        let swap = library.get_component_function("swap").unwrap();
+        assert_eq!(swap.call(&mut library, &[point(1, 2)]), vec![point(2, 1)]);
+
+        let count = library.get_component_function("count").unwrap();
+        let list = Val::List(vec![Val::U32(7); 3]);
+        assert_eq!(count.call(&mut library, &[list]), vec![Val::U32(3)]);
+
+        // The memory of the component is not exported, but it is counted.
+        assert!(library.memory_usage().unwrap() >= 0x10000);
+
+        let output = add.try_call_captured(&mut library, &[Val::U32(2), Val::U32(2)]);
+        assert_eq!(output.result.unwrap(), vec![Val::U32(4)]);
+        assert!(output.stdout.is_empty());
+
+        // A call that never enters the component leaves it usable.
+        assert!(matches!(
+            add.try_call(&mut library, &[Val::U32(1)]),
+            Err(CallError::Other(_))
+        ));
+        assert!(!library.is_poisoned());
+
+        let fail = library.get_component_function("fail").unwrap();
+        assert!(matches!(
+            fail.try_call(&mut library, &[]),
+            Err(CallError::Trap {
+                code: Trap::UnreachableCodeReached,
+                ..
+            })
+        ));
+        assert!(library.is_poisoned());
+        assert!(matches!(
+            add.try_call(&mut library, &[Val::U32(1), Val::U32(2)]),
+            Err(CallError::Poisoned)
+        ));
+    }
+}
@@ -0,0 +1,91 @@
+//! Compression formats of published libraries.
+//!
+//! A library may be published gzip, zstd or xz compressed, declared by the `compression` field
+//! of its manifest entry or by the extension of its URL. A server may also compress the transfer
+//! with a `Content-Encoding`. Either way, the cache only holds the decompressed file.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+/// A compression format that a library file can be published in.
+/// Compressed libraries are decompressed while they are downloaded into the cache.
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    /// The file extension used for this compression format, including the dot.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Compression::Gzip => ".gz",
+            Compression::Zstd => ".zst",
+            Compression::Xz => ".xz",
+        }
+    }
+
+    /// Detects the compression format from the extension of a file name.
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        [Compression::Gzip, Compression::Zstd, Compression::Xz]
+            .into_iter()
+            .find(|c| name.ends_with(c.extension()))
+    }
+
+    /// Detects the compression format from an HTTP `Content-Encoding` value.
+    pub fn from_content_encoding(encoding: &str) -> Option<Self> {
+        match encoding.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Compression::Gzip),
+            "zstd" => Some(Compression::Zstd),
+            "xz" => Some(Compression::Xz),
+            _ => None,
+        }
+    }
+
+    /// Wraps `reader` so that it yields the decompressed content.
+    pub fn decoder<'a>(&self, reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Compression::Gzip => Box::new(flate2::read::MultiGzDecoder::new(reader)),
+            Compression::Zstd => Box::new(zstd::stream::read::Decoder::new(reader)?),
+            Compression::Xz => Box::new(xz2::read::XzDecoder::new(reader)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_from_file_name() {
+        assert_eq!(
+            Compression::from_file_name("libadder.so.gz"),
+            Some(Compression::Gzip)
+        );
+        assert_eq!(
+            Compression::from_file_name("adder.wasm.zst"),
+            Some(Compression::Zstd)
+        );
+        assert_eq!(Compression::from_file_name("adder.dll"), None);
+    }
+
+    #[test]
+    fn test_gzip_decoder() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut decoded = String::new();
+        Compression::Gzip
+            .decoder(compressed.as_slice())
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+
+        assert_eq!(decoded, "hello");
+    }
+}
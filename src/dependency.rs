@@ -1,3 +1,6 @@
+use crate::compression::Compression;
+use crate::dllpack_file::LibSpec;
+use semver::VersionReq;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
@@ -19,6 +22,16 @@ pub enum Dependency {
         /// If not provided, the filename from the URL will be used
         #[serde(default)]
         name: Option<String>,
+        /// Optional hex encoded SHA-256 of the library file (after decompression, if compressed)
+        #[serde(default)]
+        sha256: Option<String>,
+        /// Optional hex encoded SHA-256 of the file as published, before decompression
+        #[serde(default, rename = "compressed-sha256")]
+        compressed_sha256: Option<String>,
+        /// Optional compression format of the published file.
+        /// If not provided, it is detected from the file extension of the URL
+        #[serde(default)]
+        compression: Option<Compression>,
     },
 
     /// A packaged dllpack file that contains a library along with its manifest
@@ -29,5 +42,31 @@ pub enum Dependency {
         /// URL where the .dllpack file can be downloaded from
         #[serde(with = "url_serde")]
         url: Url,
+        /// Optional requirement on the version declared in the metadata of the dllpack.
+        /// Resolution fails if the dllpack does not satisfy it
+        #[serde(default)]
+        version: Option<VersionReq>,
     },
 }
+
+impl Dependency {
+    /// The declaration of the library, if this is a `rawlib` dependency.
+    pub fn lib_spec(&self) -> Option<LibSpec<'_>> {
+        match self {
+            Dependency::RawLib {
+                url,
+                name,
+                sha256,
+                compressed_sha256,
+                compression,
+            } => Some(LibSpec {
+                url,
+                name: name.as_deref(),
+                sha256: sha256.as_deref(),
+                compressed_sha256: compressed_sha256.as_deref(),
+                compression: *compression,
+            }),
+            Dependency::DllPack { .. } => None,
+        }
+    }
+}
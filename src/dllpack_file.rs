@@ -1,6 +1,11 @@
+use crate::cache;
+use crate::compression::Compression;
 use crate::dependency::Dependency;
+use crate::fetch_policy::join_reference;
 use anyhow::{anyhow, Result};
+use semver::Version;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use std::collections::BTreeMap;
 use url::Url;
 
@@ -18,16 +23,93 @@ pub struct PlatformManifest {
     #[serde(default)]
     pub name: Option<String>,
 
+    /// Optional hex encoded SHA-256 of the library file (after decompression, if compressed).
+    /// If provided, the downloaded file is verified against it,
+    /// and the download is skipped if the same content is already cached.
+    #[serde(default)]
+    pub sha256: Option<String>,
+
+    /// Optional hex encoded SHA-256 of the file as published, before decompression.
+    #[serde(default, rename = "compressed-sha256")]
+    pub compressed_sha256: Option<String>,
+
+    /// Optional compression format of the published file.
+    /// If not provided, it is detected from the file extension of the URL.
+    #[serde(default)]
+    pub compression: Option<Compression>,
+
     #[serde(default)]
     pub dependencies: Vec<Dependency>,
 }
 
+impl PlatformManifest {
+    /// The declaration of the main library of this platform.
+    pub fn lib_spec(&self) -> LibSpec<'_> {
+        LibSpec {
+            url: &self.url,
+            name: self.name.as_deref(),
+            sha256: self.sha256.as_deref(),
+            compressed_sha256: self.compressed_sha256.as_deref(),
+            compression: self.compression,
+        }
+    }
+}
+
+/// A borrowed view of a library declared in a manifest,
+/// either as the main library of a platform or as a `rawlib` dependency.
+#[derive(Debug, Clone, Copy)]
+pub struct LibSpec<'a> {
+    pub url: &'a Url,
+    pub name: Option<&'a str>,
+    pub sha256: Option<&'a str>,
+    pub compressed_sha256: Option<&'a str>,
+    pub compression: Option<Compression>,
+}
+
 /// A struct that stores a PlatformManifest for each respective platform.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Manifest {
     pub platforms: BTreeMap<String, PlatformManifest>,
 }
 
+/// Information about the package that a dllpack file provides.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metadata {
+    /// The name of the package.
+    /// Dllpacks with the same name are treated as versions of the same package,
+    /// and only one of them can be loaded in a dependency graph.
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// The version of the package, in semver format.
+    #[serde(default)]
+    pub version: Option<Version>,
+
+    /// A short, human readable description of the package.
+    #[serde(default)]
+    pub description: Option<String>,
+
+    /// The license of the package, as an SPDX license expression such as `MIT OR Apache-2.0`.
+    #[serde(default)]
+    pub license: Option<String>,
+
+    /// The authors of the package, conventionally in the form `Name <email>`.
+    #[serde(default)]
+    pub authors: Vec<String>,
+
+    /// The URL of the source repository.
+    #[serde(default, with = "url_serde")]
+    pub repository: Option<Url>,
+
+    /// The URL of the homepage or documentation.
+    #[serde(default, with = "url_serde")]
+    pub homepage: Option<Url>,
+
+    /// Keywords that describe the package, for searching.
+    #[serde(default)]
+    pub keywords: Vec<String>,
+}
+
 ///　A struct corresponding to the top level of a dllpack file,
 /// consisting of a `Manifest` that stores specific information and
 /// a `spec_version` that indicates the version of the dllpack file specification.
@@ -42,19 +124,103 @@ pub struct DllPackFile {
     #[serde(rename = "spec-version")]
     pub spec_version: String,
 
+    /// Optional information about the package.
+    #[serde(default)]
+    pub metadata: Option<Metadata>,
+
     pub manifest: Manifest,
 }
 
+/// Applies `f` to every URL string in a dllpack file given as a JSON value,
+/// that is, the `url` of every platform and of every dependency.
+pub(crate) fn map_urls(value: &mut Value, mut f: impl FnMut(&str) -> Result<String>) -> Result<()> {
+    let Some(platforms) = value
+        .pointer_mut("/manifest/platforms")
+        .and_then(Value::as_object_mut)
+    else {
+        return Ok(());
+    };
+
+    for p_manifest in platforms.values_mut() {
+        if let Some(Value::String(url)) = p_manifest.get_mut("url") {
+            *url = f(url)?;
+        }
+
+        let Some(dependencies) = p_manifest
+            .get_mut("dependencies")
+            .and_then(Value::as_array_mut)
+        else {
+            continue;
+        };
+
+        for dep in dependencies {
+            if let Some(Value::String(url)) = dep.get_mut("url") {
+                *url = f(url)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 impl DllPackFile {
     pub fn from_str(s: &str) -> Result<Self> {
         let res: DllPackFile = serde_json::from_str(s)?;
-        if res.spec_version != "1.0.0" {
-            return Err(anyhow!("Unsupported spec version: {}", res.spec_version));
-        }
+        res.check_spec_version()?;
+        res.check_hashes()?;
+
+        Ok(res)
+    }
+
+    /// Parses a dllpack file whose URLs may be relative.
+    /// Relative URLs are resolved against `base`, the URL the file was fetched from.
+    /// `file` URLs are refused unless `base` is a `file` URL itself.
+    pub fn from_str_with_base(s: &str, base: &Url) -> Result<Self> {
+        let mut value: Value = serde_json::from_str(s)?;
+        map_urls(&mut value, |url| Ok(join_reference(base, url)?.to_string()))?;
+
+        let res: DllPackFile = serde_json::from_value(value)?;
+        res.check_spec_version()?;
+        res.check_hashes()?;
 
         Ok(res)
     }
 
+    fn check_spec_version(&self) -> Result<()> {
+        if self.spec_version != "1.0.0" {
+            return Err(anyhow!("Unsupported spec version: {}", self.spec_version));
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the declared hashes are hex encoded SHA-256s.
+    /// They name files in the blob store of the cache, so anything else must not get through.
+    fn check_hashes(&self) -> Result<()> {
+        for p_manifest in self.manifest.platforms.values() {
+            let specs = std::iter::once(p_manifest.lib_spec()).chain(
+                p_manifest
+                    .dependencies
+                    .iter()
+                    .filter_map(Dependency::lib_spec),
+            );
+
+            for spec in specs {
+                for hash in [spec.sha256, spec.compressed_sha256].into_iter().flatten() {
+                    if !cache::is_sha256_hex(hash) {
+                        return Err(anyhow!(
+                            "Invalid SHA-256 of {}: {:?} (expected 64 lowercase hex digits)",
+                            spec.url,
+                            hash
+                        ));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn to_string(&self) -> Result<String> {
         serde_json::to_string(self).map_err(Into::into)
     }
@@ -63,4 +229,105 @@ impl DllPackFile {
         let s = std::fs::read_to_string(path)?;
         Self::from_str(&s)
     }
+
+    /// Reads a dllpack file whose URLs may be relative to `base`.
+    pub fn from_file_with_base<P: AsRef<std::path::Path>>(path: P, base: &Url) -> Result<Self> {
+        let s = std::fs::read_to_string(path)?;
+        Self::from_str_with_base(&s, base)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_relative_urls() {
+        let s = r#"{
+            "spec-version": "1.0.0",
+            "manifest": {
+                "platforms": {
+                    "wasm32-wasip1": {
+                        "url": "libs/adder.wasm",
+                        "dependencies": [
+                            {"type": "dllpack", "url": "../dep.dllpack"},
+                            {"type": "rawlib", "url": "https://example.com/raw.wasm"}
+                        ]
+                    }
+                }
+            }
+        }"#;
+        let base = Url::from_str("file:///srv/packs/adder.dllpack").unwrap();
+
+        let file = DllPackFile::from_str_with_base(s, &base).unwrap();
+        let p_manifest = &file.manifest.platforms["wasm32-wasip1"];
+
+        assert_eq!(p_manifest.url.as_str(), "file:///srv/packs/libs/adder.wasm");
+        assert!(matches!(
+            &p_manifest.dependencies[0],
+            Dependency::DllPack { url, .. } if url.as_str() == "file:///srv/dep.dllpack"
+        ));
+        assert!(matches!(
+            &p_manifest.dependencies[1],
+            Dependency::RawLib { url, .. } if url.as_str() == "https://example.com/raw.wasm"
+        ));
+    }
+
+    #[test]
+    fn test_invalid_sha256() {
+        let file = |sha256: &str| {
+            format!(
+                r#"{{
+                    "spec-version": "1.0.0",
+                    "manifest": {{ "platforms": {{ "wasm32-wasip1": {{
+                        "url": "https://example.com/adder.wasm",
+                        "dependencies": [{{
+                            "type": "rawlib",
+                            "url": "https://example.com/raw.wasm",
+                            "sha256": "{}"
+                        }}]
+                    }} }} }}
+                }}"#,
+                sha256
+            )
+        };
+
+        assert!(DllPackFile::from_str(&file(&"ab".repeat(32))).is_ok());
+        assert!(DllPackFile::from_str(&file(&"AB".repeat(32))).is_err());
+        assert!(DllPackFile::from_str(&file("../../x")).is_err());
+        assert!(DllPackFile::from_str(&file(&format!("/{}", "a".repeat(63)))).is_err());
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let s = r#"{
+            "spec-version": "1.0.0",
+            "metadata": {
+                "name": "adder",
+                "version": "1.2.0",
+                "description": "Adds two numbers",
+                "license": "MIT OR Apache-2.0",
+                "authors": ["Jane Doe <jane@example.com>"],
+                "repository": "https://github.com/example/adder",
+                "keywords": ["math"]
+            },
+            "manifest": { "platforms": {} }
+        }"#;
+
+        let file = DllPackFile::from_str(s).unwrap();
+        let file = DllPackFile::from_str(&file.to_string().unwrap()).unwrap();
+        let metadata = file.metadata.unwrap();
+
+        assert_eq!(metadata.name.as_deref(), Some("adder"));
+        assert_eq!(metadata.version, Some(Version::new(1, 2, 0)));
+        assert_eq!(metadata.license.as_deref(), Some("MIT OR Apache-2.0"));
+        assert_eq!(metadata.authors, vec!["Jane Doe <jane@example.com>"]);
+        assert_eq!(
+            metadata.repository.unwrap().as_str(),
+            "https://github.com/example/adder"
+        );
+        assert_eq!(metadata.homepage, None);
+        assert_eq!(metadata.keywords, vec!["math"]);
+    }
 }
@@ -1,12 +1,232 @@
+use crate::bundle;
+use crate::cache;
+use crate::cache::CacheLayers;
+use crate::compression::Compression;
+use crate::dllpack_file::{DllPackFile, LibSpec};
+use crate::fetch_policy::{fetch_policy, FetchPolicy};
 use anyhow::{anyhow, Result};
 use log::{debug, trace};
-use reqwest;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::fs;
 use std::fs::DirBuilder;
-use std::io::Write;
-use std::path::PathBuf;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
 use url::Url;
-use urlencoding;
+
+/// A client that follows redirects as allowed by `policy`.
+fn client(policy: &FetchPolicy) -> Result<reqwest::blocking::Client> {
+    let policy = policy.clone();
+
+    let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > policy.max_redirects {
+            return attempt.error(format!(
+                "too many redirects (more than {})",
+                policy.max_redirects
+            ));
+        }
+
+        let target = attempt.url().clone();
+        if let Err(e) = policy.check_parts(target.scheme(), target.host_str()) {
+            return attempt.error(format!("redirect to {} is not allowed: {}", target, e));
+        }
+
+        let original_host = attempt.previous().first().and_then(|u| u.host_str());
+        if !policy.allow_cross_host_redirects && target.host_str() != original_host {
+            return attempt.error(format!("cross-host redirect to {} is not allowed", target));
+        }
+
+        attempt.follow()
+    });
+
+    Ok(reqwest::blocking::Client::builder()
+        .redirect(redirect)
+        .build()?)
+}
+
+/// Sends a request, keeping the reason of a refused redirect in the error message.
+fn send(req: reqwest::blocking::RequestBuilder) -> Result<reqwest::blocking::Response> {
+    req.send().map_err(|e| match std::error::Error::source(&e) {
+        Some(source) => anyhow!("{}: {}", e, source),
+        None => e.into(),
+    })
+}
+
+/// Opens a stream of the content of `url`.
+/// Besides `http` and `https`, local `file` URLs are supported.
+///
+/// A `Content-Encoding` applied by the server is decoded transparently.
+/// The URL and any redirects must be allowed by the [fetch policy](crate::fetch_policy).
+pub fn fetch_reader(url: &Url) -> Result<Box<dyn Read>> {
+    let (body, encoding) = fetch_raw_reader(url)?;
+
+    match encoding {
+        Some(compression) => compression.decoder(body),
+        None => Ok(body),
+    }
+}
+
+/// Like [`fetch_reader`], but the body is returned as sent by the server,
+/// together with its `Content-Encoding`, if any.
+fn fetch_raw_reader(url: &Url) -> Result<(Box<dyn Read>, Option<Compression>)> {
+    let policy = fetch_policy();
+    policy.check_url(url)?;
+
+    if url.scheme() == "file" {
+        return Ok((Box::new(open_file_url(url)?), None));
+    }
+
+    let res = send(client(&policy)?.get(url.as_str()))?;
+
+    if !res.status().is_success() {
+        return Err(anyhow!("Failed to download {}: {}", url, res.status()));
+    }
+
+    let encoding = content_encoding(&res);
+    Ok((Box::new(res), encoding))
+}
+
+fn open_file_url(url: &Url) -> Result<fs::File> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| anyhow!("Invalid file URL: {}", url))?;
+
+    fs::File::open(&path).map_err(|e| anyhow!("Failed to read {}: {}", url, e))
+}
+
+/// The `Content-Encoding` of a response, if it is a supported one.
+fn content_encoding(res: &reqwest::blocking::Response) -> Option<Compression> {
+    res.headers()
+        .get(reqwest::header::CONTENT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Compression::from_content_encoding)
+}
+
+/// Wraps the body of a response so that its `Content-Encoding` is decoded.
+fn decode_response(res: reqwest::blocking::Response) -> Result<Box<dyn Read>> {
+    match content_encoding(&res) {
+        Some(compression) => compression.decoder(res),
+        None => Ok(Box::new(res)),
+    }
+}
+
+/// Validators of a previously fetched response, used to revalidate it with a conditional request.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default)]
+    pub etag: Option<String>,
+    #[serde(default, rename = "last-modified")]
+    pub last_modified: Option<String>,
+}
+
+/// The result of `fetch_revalidate`.
+#[derive(Debug)]
+pub enum Revalidated {
+    /// The content has not changed since it was fetched with the given validators.
+    NotModified,
+    /// The (new) content, with its validators.
+    Modified(Vec<u8>, Validators),
+}
+
+/// Fetches the content of `url` unless it is unchanged since it was fetched with `validators`,
+/// using `If-None-Match` and `If-Modified-Since`.
+/// Local `file` URLs are cheap to read and are always fetched.
+pub fn fetch_revalidate(url: &Url, validators: &Validators) -> Result<Revalidated> {
+    let policy = fetch_policy();
+    policy.check_url(url)?;
+
+    if url.scheme() == "file" {
+        let mut content = Vec::new();
+        open_file_url(url)?.read_to_end(&mut content)?;
+
+        return Ok(Revalidated::Modified(content, Validators::default()));
+    }
+
+    let mut req = client(&policy)?.get(url.as_str());
+    if let Some(etag) = &validators.etag {
+        req = req.header(reqwest::header::IF_NONE_MATCH, etag);
+    }
+    if let Some(last_modified) = &validators.last_modified {
+        req = req.header(reqwest::header::IF_MODIFIED_SINCE, last_modified);
+    }
+
+    let res = send(req)?;
+
+    if res.status() == reqwest::StatusCode::NOT_MODIFIED {
+        return Ok(Revalidated::NotModified);
+    }
+    if !res.status().is_success() {
+        return Err(anyhow!("Failed to download {}: {}", url, res.status()));
+    }
+
+    let header = |name| {
+        res.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string)
+    };
+    let validators = Validators {
+        etag: header(reqwest::header::ETAG),
+        last_modified: header(reqwest::header::LAST_MODIFIED),
+    };
+
+    let mut content = Vec::new();
+    decode_response(res)?.read_to_end(&mut content)?;
+
+    Ok(Revalidated::Modified(content, validators))
+}
+
+/// Fetches the content of `url`.
+/// Besides `http` and `https`, local `file` URLs are supported.
+pub fn fetch(url: &Url) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    fetch_reader(url)?.read_to_end(&mut content)?;
+
+    Ok(content)
+}
+
+/// A reader that computes the SHA-256 of everything read through it.
+struct HashingReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashingReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn hex_digest(self) -> String {
+        cache::hex(&self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for HashingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn check_hash(url: &Url, what: &str, expected: &Option<String>, actual: &str) -> Result<()> {
+    if let Some(expected) = expected {
+        if expected != actual {
+            return Err(anyhow!(
+                "Hash mismatch for {}: expected {} {}, got {}",
+                url,
+                what,
+                expected,
+                actual
+            ));
+        }
+    }
+
+    Ok(())
+}
 
 /// Metadata about the source of the raw DLL (.so, .dll) and where it will be downloaded.
 #[derive(Debug, Clone, Ord, PartialOrd, Eq, PartialEq)]
@@ -15,11 +235,60 @@ pub struct DllInfo {
     pub name: String,
     pub path: PathBuf,
     pub cache_dir: Option<PathBuf>,
+    /// Path of the cache index entry that maps `cache_dir` back to `url`.
+    pub index_path: PathBuf,
+    /// The SHA-256 of the (decompressed) library declared by the manifest, if any.
+    pub sha256: Option<String>,
+    /// The SHA-256 of the published, compressed file declared by the manifest, if any.
+    pub compressed_sha256: Option<String>,
+    /// The compression of the published file, declared or detected from the URL.
+    pub compression: Option<Compression>,
+    /// The directory of the content-addressed blob store.
+    pub blobs_dir: PathBuf,
+}
+
+/// The file name of the module cache of the library `name`.
+/// Modules compiled for engines with and without fuel differ, so they are cached separately.
+pub(crate) fn module_cache_file_name(name: &str, consume_fuel: bool) -> String {
+    if consume_fuel {
+        format!("module-cache-{}.fuel.bin", name)
+    } else {
+        format!("module-cache-{}.bin", name)
+    }
+}
+
+fn last_url_segment(url: &Url) -> Option<&str> {
+    url.path_segments().and_then(|s| s.last())
+}
+
+/// The name a library downloaded from `url` is stored under.
+/// A compressed file is stored decompressed, so the extension of the compression is dropped.
+fn stored_file_name(url: &Url, compression: Option<Compression>) -> Result<&str> {
+    let last = last_url_segment(url).ok_or(anyhow!("Could not get file name"))?;
+
+    Ok(compression
+        .and_then(|c| last.strip_suffix(c.extension()))
+        .unwrap_or(last))
 }
 
 impl DllInfo {
+    /// A library without declared hashes, stored at `path` in `cache_dir`.
+    /// The cache index and the blob store are those of the work directory that holds `cache_dir`.
     pub fn new(url: Url, name: String, path: PathBuf, cache_dir: Option<PathBuf>) -> Self {
+        let work_dir = cache_dir
+            .as_deref()
+            .or(path.parent())
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        let key = cache::url_key(&url);
+
         Self {
+            index_path: cache::index_path(&work_dir, &key),
+            blobs_dir: cache::blobs_dir(&work_dir),
+            sha256: None,
+            compressed_sha256: None,
+            compression: None,
             url,
             name,
             path,
@@ -27,30 +296,84 @@ impl DllInfo {
         }
     }
 
+    /// A library without declared hashes, named `name` or after the last segment of `url`.
+    /// Its compression is inferred from the file extension of `url`.
     pub fn from_input(url: &Url, name: &Option<&str>, dir_path: &PathBuf) -> Result<Self> {
-        let e_url = urlencoding::encode(url.as_str());
+        let compression = last_url_segment(url).and_then(Compression::from_file_name);
+        let name = match name {
+            Some(name) => name,
+            None => stored_file_name(url, compression)?,
+        };
 
-        let last_of_url_path = url.path_segments().and_then(|s| s.last());
-        let name = name
-            .or(last_of_url_path)
-            .ok_or(anyhow!("Could not get file name"))?;
-
-        let cache_dir = dir_path.join(e_url.to_string());
+        let cache_dir = dir_path.join(cache::url_key(url));
         let path = cache_dir.join(name);
 
-        Ok(Self::new(
-            url.clone(),
-            name.to_string(),
-            path,
-            Some(cache_dir),
-        ))
+        Ok(Self {
+            compression,
+            ..Self::new(url.clone(), name.to_string(), path, Some(cache_dir))
+        })
+    }
+
+    /// A library as declared in a manifest, with its hashes and compression.
+    pub fn from_spec(spec: &LibSpec, dir_path: &PathBuf) -> Result<Self> {
+        let compression = spec
+            .compression
+            .or(last_url_segment(spec.url).and_then(Compression::from_file_name));
+        let name = match spec.name {
+            Some(name) => name,
+            None => stored_file_name(spec.url, compression)?,
+        };
+
+        Ok(Self {
+            sha256: spec.sha256.map(str::to_string),
+            compressed_sha256: spec.compressed_sha256.map(str::to_string),
+            compression,
+            ..Self::from_input(spec.url, &Some(name), dir_path)?
+        })
     }
 
+    /// Returns the first layer's copy of the library described by `spec`,
+    /// or where it is to be downloaded in the writable layer if no layer has it.
+    pub fn from_layers(spec: &LibSpec, layers: &CacheLayers) -> Result<Self> {
+        for dir in layers.lookup_dirs() {
+            let info = Self::from_spec(spec, dir)?;
+            if info.path.exists() {
+                return Ok(info);
+            }
+        }
+
+        let dir = layers.writable_dir().ok_or(anyhow!(
+            "{} is not cached and no writable cache layer is available",
+            spec.url
+        ))?;
+        Self::from_spec(spec, dir)
+    }
+
+    /// The path of the declared content in the blob store, if the manifest declares a hash.
+    /// It fails if the declared hash is not a hex encoded SHA-256.
+    pub fn declared_blob_path(&self) -> Result<Option<PathBuf>> {
+        let Some(hash) = &self.sha256 else {
+            return Ok(None);
+        };
+
+        if !cache::is_sha256_hex(hash) {
+            return Err(anyhow!("Invalid SHA-256 of {}: {:?}", self.url, hash));
+        }
+
+        Ok(Some(self.blobs_dir.join(hash)))
+    }
+
+    /// The module cache of the library, for an engine that does not consume fuel.
     pub fn wasm_module_cache_path(&self) -> PathBuf {
+        self.module_cache_path(false)
+    }
+
+    /// The module cache of the library, for an engine that does (or does not) consume fuel.
+    pub fn module_cache_path(&self, consume_fuel: bool) -> PathBuf {
         self.path
             .parent()
             .unwrap()
-            .join(format!("module-cache-{}.bin", self.name))
+            .join(module_cache_file_name(&self.name, consume_fuel))
     }
 
     pub fn exist_cache_dir(&self) -> Option<PathBuf> {
@@ -65,26 +388,78 @@ impl DllInfo {
 }
 
 pub fn download_lib(dll_info: &DllInfo) -> Result<()> {
+    // The content is already known locally, possibly under another URL.
+    if let Some(blob_path) = dll_info.declared_blob_path()? {
+        if blob_path.exists() {
+            debug!(
+                "found in blob store: {} ({})",
+                dll_info.url,
+                blob_path.display()
+            );
+            cache::link_or_copy(&blob_path, &dll_info.path)?;
+            cache::record_url(&dll_info.index_path, &dll_info.url)?;
+
+            return Ok(());
+        }
+    }
+
     debug!("downloading: {}", dll_info.path.display());
 
-    let res = reqwest::blocking::get(dll_info.url.as_str())?;
+    fs::create_dir_all(&dll_info.blobs_dir)?;
+    let tmp_path = cache::temp_path(&dll_info.blobs_dir, "download");
+
+    // The compression of the artifact is the only one applied. Servers often label
+    // compressed files with a matching `Content-Encoding`, which must not be decoded again.
+    // Otherwise, the `Content-Encoding` is a transport encoding of the published file.
+    let (body, encoding) = fetch_raw_reader(&dll_info.url)?;
+    let body = match (dll_info.compression, encoding) {
+        (None, Some(encoding)) => encoding.decoder(body)?,
+        _ => body,
+    };
+
+    // Stream the (decompressed) content into a temporary file in the blob store,
+    // hashing it both as published and after decompression.
+    let mut published = HashingReader::new(body);
+    let hash = {
+        let decoded: Box<dyn Read + '_> = match dll_info.compression {
+            Some(compression) => compression.decoder(&mut published)?,
+            None => Box::new(&mut published),
+        };
+        let mut decoded = HashingReader::new(decoded);
+
+        let mut file = fs::File::create(&tmp_path)?;
+        let copied = std::io::copy(&mut decoded, &mut file);
+        if let Err(e) = copied {
+            fs::remove_file(&tmp_path)?;
+            return Err(anyhow!("Failed to download {}: {}", dll_info.url, e));
+        }
 
-    if !res.status().is_success() {
-        return Err(anyhow!(
-            "Failed to download {}: {}",
-            dll_info.url,
-            res.status()
-        ));
+        decoded.hex_digest()
+    };
+    let published_hash = published.hex_digest();
+
+    let checked = check_hash(&dll_info.url, "sha256", &dll_info.sha256, &hash).and_then(|_| {
+        check_hash(
+            &dll_info.url,
+            "compressed-sha256",
+            &dll_info.compressed_sha256,
+            &published_hash,
+        )
+    });
+    if let Err(e) = checked {
+        fs::remove_file(&tmp_path)?;
+        return Err(e);
     }
 
-    DirBuilder::new()
-        .recursive(true)
-        .create(dll_info.path.parent().unwrap())?;
-
-    let mut file = fs::File::create(&dll_info.path)?;
+    let blob_path = dll_info.blobs_dir.join(&hash);
+    if blob_path.exists() {
+        fs::remove_file(&tmp_path)?;
+    } else {
+        fs::rename(&tmp_path, &blob_path)?;
+    }
+    cache::link_or_copy(&blob_path, &dll_info.path)?;
 
-    let content = res.bytes()?;
-    file.write_all(&content)?;
+    cache::record_url(&dll_info.index_path, &dll_info.url)?;
 
     Ok(())
 }
@@ -104,42 +479,127 @@ pub fn cached_download_lib(dll_info: &DllInfo) -> Result<()> {
 pub struct ManifestInfo {
     pub url: Url,
     pub path: PathBuf,
+    /// Path of the cache index entry that maps `path` back to `url`.
+    pub index_path: PathBuf,
+    /// The directory that bundles are unpacked into, if a cache layer is writable.
+    pub bundles_dir: Option<PathBuf>,
+    /// The directories that bundles may already be unpacked in, in lookup order.
+    pub bundle_lookup_dirs: Vec<PathBuf>,
 }
 
 impl ManifestInfo {
+    /// The manifest of `url` stored at `path`, in the manifests directory of a work directory.
     pub fn new(url: Url, path: PathBuf) -> Self {
-        Self { url, path }
+        let work_dir = path
+            .parent()
+            .and_then(Path::parent)
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        let key = cache::url_key(&url);
+        let bundles_dir = work_dir.join(cache::BUNDLES_DIR);
+
+        Self {
+            url,
+            path,
+            index_path: cache::index_path(&work_dir, &key),
+            bundle_lookup_dirs: vec![bundles_dir.clone()],
+            bundles_dir: Some(bundles_dir),
+        }
     }
 
     pub fn from_input(url: &Url, dir_path: &PathBuf) -> Result<Self> {
-        let e_url = urlencoding::encode(url.as_str());
-        let path = dir_path.join("_manifests").join(e_url.to_string());
+        let path = dir_path
+            .join(cache::MANIFESTS_DIR)
+            .join(cache::url_key(url));
 
         Ok(Self::new(url.clone(), path))
     }
+
+    /// The manifest stored under the cache key `key`, which is normally derived from `url`.
+    pub(crate) fn from_key(url: &Url, key: &str, dir_path: &Path) -> Self {
+        let bundles_dir = dir_path.join(cache::BUNDLES_DIR);
+
+        Self {
+            url: url.clone(),
+            path: dir_path.join(cache::MANIFESTS_DIR).join(key),
+            index_path: cache::index_path(dir_path, key),
+            bundle_lookup_dirs: vec![bundles_dir.clone()],
+            bundles_dir: Some(bundles_dir),
+        }
+    }
+
+    /// Reads the cached manifest.
+    /// If it is a bundle, it is unpacked and its root manifest is returned.
+    pub fn read_file(&self) -> Result<DllPackFile> {
+        Ok(self.read_file_with_bundle_dir()?.0)
+    }
+
+    /// Like [`Self::read_file`], and also returns the directory the manifest is unpacked into
+    /// if it is a bundle.
+    pub fn read_file_with_bundle_dir(&self) -> Result<(DllPackFile, Option<PathBuf>)> {
+        if !bundle::is_bundle_file(&self.path)? {
+            let content = fs::read_to_string(&self.path)?;
+            return Ok((DllPackFile::from_str_with_base(&content, &self.url)?, None));
+        }
+
+        let content = fs::read(&self.path)?;
+        let (file, dir) = bundle::read_bundle(
+            &content,
+            &self.bundle_lookup_dirs,
+            self.bundles_dir.as_deref(),
+        )?;
+
+        Ok((file, Some(dir)))
+    }
+
+    /// Finds the cached manifest of `url` in the first layer that has it, falling back to
+    /// the writable layer. Its bundles are found unpacked in any layer and unpacked into
+    /// the writable one.
+    pub fn from_layers(url: &Url, layers: &CacheLayers) -> Result<Self> {
+        let found = layers
+            .lookup_dirs()
+            .map(|dir| Self::from_input(url, dir))
+            .find(|info| info.as_ref().map_or(true, |info| info.path.exists()));
+
+        let mut info = match found {
+            Some(info) => info?,
+            None => {
+                let dir = layers.writable_dir().ok_or(anyhow!(
+                    "{} is not cached and no writable cache layer is available",
+                    url
+                ))?;
+                Self::from_input(url, dir)?
+            }
+        };
+
+        info.bundles_dir = layers.writable_dir().map(|d| d.join(cache::BUNDLES_DIR));
+        info.bundle_lookup_dirs = layers
+            .lookup_dirs()
+            .map(|d| d.join(cache::BUNDLES_DIR))
+            .collect();
+
+        Ok(info)
+    }
 }
 
 pub fn download_manifest(manifest_info: &ManifestInfo) -> Result<()> {
     debug!("downloading: {}", manifest_info.path.display());
 
-    let res = reqwest::blocking::get(manifest_info.url.as_str())?;
+    let content = fetch(&manifest_info.url)?;
 
-    if !res.status().is_success() {
-        return Err(anyhow!(
-            "Failed to download {}: {}",
-            manifest_info.url,
-            res.status()
-        ));
-    }
+    write_manifest(manifest_info, &content)
+}
 
+/// Stores the content of a manifest that has been fetched from `manifest_info.url` in the cache.
+pub fn write_manifest(manifest_info: &ManifestInfo, content: &[u8]) -> Result<()> {
     DirBuilder::new()
         .recursive(true)
         .create(manifest_info.path.parent().unwrap())?;
 
     let mut file = fs::File::create(&manifest_info.path)?;
+    file.write_all(content)?;
 
-    let content = res.bytes()?;
-    file.write_all(&content)?;
+    cache::record_url(&manifest_info.index_path, &manifest_info.url)?;
 
     Ok(())
 }
@@ -153,3 +613,56 @@ pub fn cached_download_manifest(manifest_info: &ManifestInfo) -> Result<()> {
 
     download_manifest(manifest_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_compressed_file_with_content_encoding() {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(b"hello").unwrap();
+        let published = encoder.finish().unwrap();
+
+        // Serves the `.gz` file labeled with `Content-Encoding: gzip`, as many servers do.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let body = published.clone();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            )
+            .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+
+        let work_dir =
+            std::env::temp_dir().join(format!("dll-pack-content-encoding-{}", std::process::id()));
+        let url = Url::from_str(&format!("http://127.0.0.1:{}/libhello.so.gz", port)).unwrap();
+        let sha256 = cache::sha256_hex(b"hello");
+        let compressed_sha256 = cache::sha256_hex(&published);
+        let spec = LibSpec {
+            url: &url,
+            name: None,
+            sha256: Some(&sha256),
+            compressed_sha256: Some(&compressed_sha256),
+            compression: None,
+        };
+
+        let dll_info = DllInfo::from_spec(&spec, &work_dir).unwrap();
+        download_lib(&dll_info).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(dll_info.name, "libhello.so");
+        assert_eq!(fs::read(&dll_info.path).unwrap(), b"hello");
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+}
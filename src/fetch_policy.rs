@@ -0,0 +1,133 @@
+//! Restrictions on the URLs that dll-pack fetches.
+//!
+//! The policy is consulted for every fetched URL (manifests, libraries, channels and registries)
+//! and for every redirect target. It is configured for the whole process with [`set_fetch_policy`].
+//!
+//! Regardless of the policy, a document that is not a local file (a manifest, a channel
+//! or a registry index) cannot refer to `file` URLs; see [`join_reference`].
+
+use crate::glob::glob_match;
+use anyhow::{anyhow, Result};
+use std::sync::{LazyLock, Mutex};
+use url::Url;
+
+/// A policy that restricts which URLs may be fetched and how redirects are followed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FetchPolicy {
+    /// The allowed URL schemes, such as `https`.
+    pub allowed_schemes: Vec<String>,
+    /// Patterns of the allowed hosts, where `*` matches any sequence of characters
+    /// (for example, `*.example.com`). Any host is allowed if `None`.
+    /// `file` URLs have no host and are not restricted by this.
+    pub allowed_hosts: Option<Vec<String>>,
+    /// The maximum number of redirects followed for a single fetch.
+    pub max_redirects: usize,
+    /// Whether a redirect may lead to a host other than the one originally requested.
+    pub allow_cross_host_redirects: bool,
+}
+
+impl Default for FetchPolicy {
+    /// Allows `http`, `https` and `file` URLs of any host, and up to 10 redirects.
+    /// `file` URLs are only followed from local documents.
+    fn default() -> Self {
+        Self {
+            allowed_schemes: vec!["http".to_string(), "https".to_string(), "file".to_string()],
+            allowed_hosts: None,
+            max_redirects: 10,
+            allow_cross_host_redirects: true,
+        }
+    }
+}
+
+impl FetchPolicy {
+    /// Checks a URL given by its scheme and host.
+    /// The `url` crate of reqwest differs from ours, so URLs are not passed as a whole.
+    pub(crate) fn check_parts(&self, scheme: &str, host: Option<&str>) -> Result<(), String> {
+        if !self.allowed_schemes.iter().any(|s| s == scheme) {
+            return Err(format!("scheme `{}` is not allowed", scheme));
+        }
+
+        if let (Some(patterns), Some(host)) = (&self.allowed_hosts, host) {
+            if !patterns.iter().any(|p| glob_match(p, host)) {
+                return Err(format!("host `{}` is not allowed", host));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether `url` may be fetched.
+    pub fn check_url(&self, url: &Url) -> Result<()> {
+        self.check_parts(url.scheme(), url.host_str())
+            .map_err(|e| anyhow!("Fetching {} is not allowed by the fetch policy: {}", url, e))
+    }
+}
+
+/// Resolves `reference` against `base`, the URL of the document that refers to it.
+///
+/// Only local documents may refer to `file` URLs,
+/// so a remote manifest cannot make dll-pack read files of the local machine.
+pub(crate) fn join_reference(base: &Url, reference: &str) -> Result<Url> {
+    let url = base.join(reference)?;
+    if url.scheme() == "file" && base.scheme() != "file" {
+        return Err(anyhow!(
+            "{} refers to the local file {}, which only local documents may do",
+            base,
+            url
+        ));
+    }
+
+    Ok(url)
+}
+
+static FETCH_POLICY: LazyLock<Mutex<FetchPolicy>> =
+    LazyLock::new(|| Mutex::new(FetchPolicy::default()));
+
+/// Sets the fetch policy used by every fetch in this process.
+pub fn set_fetch_policy(policy: FetchPolicy) {
+    *FETCH_POLICY.lock().unwrap() = policy;
+}
+
+/// The fetch policy currently in effect.
+pub fn fetch_policy() -> FetchPolicy {
+    FETCH_POLICY.lock().unwrap().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_check_url() {
+        let policy = FetchPolicy {
+            allowed_schemes: vec!["https".to_string(), "file".to_string()],
+            allowed_hosts: Some(vec!["*.example.com".to_string()]),
+            ..Default::default()
+        };
+
+        let check = |url: &str| policy.check_url(&Url::from_str(url).unwrap());
+
+        assert!(check("https://cdn.example.com/adder.dllpack").is_ok());
+        assert!(check("file:///srv/adder.dllpack").is_ok());
+        assert!(check("http://cdn.example.com/adder.dllpack").is_err());
+        assert!(check("https://evil.com/adder.dllpack").is_err());
+    }
+
+    #[test]
+    fn test_join_reference() {
+        let remote = Url::from_str("https://example.com/adder/adder.dllpack").unwrap();
+        let local = Url::from_str("file:///srv/adder/adder.dllpack").unwrap();
+
+        assert_eq!(
+            join_reference(&remote, "adder.wasm").unwrap().as_str(),
+            "https://example.com/adder/adder.wasm"
+        );
+        assert!(join_reference(&remote, "file:///etc/passwd").is_err());
+        assert_eq!(
+            join_reference(&local, "adder.wasm").unwrap().as_str(),
+            "file:///srv/adder/adder.wasm"
+        );
+        assert!(join_reference(&local, "https://example.com/adder.wasm").is_ok());
+    }
+}
@@ -0,0 +1,48 @@
+//! Glob patterns, as used for the URLs of advisories and the hosts of the fetch policy.
+
+/// Matches `s` against `pattern`, where `*` matches any sequence of characters.
+pub(crate) fn glob_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = s.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No `*` in the pattern.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+
+    rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match(
+            "https://a.com/*/lib.so",
+            "https://a.com/1.0/lib.so"
+        ));
+        assert!(glob_match("https://a.com/*", "https://a.com/"));
+        assert!(!glob_match(
+            "https://a.com/*/lib.so",
+            "https://a.com/1.0/lib.dll"
+        ));
+        assert!(glob_match("https://a.com/lib.so", "https://a.com/lib.so"));
+        assert!(!glob_match(
+            "https://a.com/lib.so",
+            "https://a.com/lib.so.1"
+        ));
+    }
+}
@@ -0,0 +1,281 @@
+use crate::cache;
+use crate::dependency::Dependency;
+use crate::dllpack_file::{LibSpec, Metadata, PlatformManifest};
+use crate::download::{DllInfo, ManifestInfo};
+use anyhow::Result;
+use log::{debug, warn};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use url::Url;
+
+/// A raw library file (.so, .dll, .wasm) referenced by a cached dllpack.
+#[derive(Debug, Clone)]
+pub struct CachedArtifact {
+    pub url: Url,
+    pub name: String,
+    /// Where the artifact is (or would be) stored in the cache.
+    pub path: PathBuf,
+    /// Size of the artifact file in bytes, or `None` if it has not been downloaded.
+    pub size: Option<u64>,
+    /// Whether a compiled wasm module cache exists next to the artifact.
+    pub has_wasm_module_cache: bool,
+    pub last_access: Option<SystemTime>,
+}
+
+/// The contents of a cached dllpack for one platform.
+#[derive(Debug, Clone)]
+pub struct CachedPlatform {
+    pub platform: String,
+    /// The main library of this platform, followed by its `rawlib` dependencies.
+    pub artifacts: Vec<CachedArtifact>,
+    /// URLs of the dllpacks this platform depends on.
+    pub dependencies: Vec<Url>,
+}
+
+/// A dllpack manifest found in the cache.
+#[derive(Debug, Clone)]
+pub struct CachedPack {
+    pub url: Url,
+    pub manifest_path: PathBuf,
+    /// `true` if no other cached dllpack depends on this one,
+    /// i.e. it was most likely loaded directly by a user.
+    pub is_root: bool,
+    /// The metadata of the dllpack, if its manifest has any.
+    pub metadata: Option<Metadata>,
+    pub platforms: Vec<CachedPlatform>,
+    /// The most recent access time of the manifest or any of its artifacts.
+    pub last_access: Option<SystemTime>,
+}
+
+impl CachedPack {
+    /// Total size in bytes of all downloaded artifacts of this dllpack.
+    pub fn artifacts_size(&self) -> u64 {
+        self.platforms
+            .iter()
+            .flat_map(|p| p.artifacts.iter())
+            .filter_map(|a| a.size)
+            .sum()
+    }
+}
+
+/// Returns the access time of a file, falling back to the modification time
+/// on file systems that do not record access times.
+fn last_access_of(path: &Path) -> Option<SystemTime> {
+    let metadata = fs::metadata(path).ok()?;
+    metadata.accessed().or_else(|_| metadata.modified()).ok()
+}
+
+fn inspect_artifact(spec: &LibSpec, work_dir: &PathBuf) -> Result<CachedArtifact> {
+    let dll_info = DllInfo::from_spec(spec, work_dir)?;
+    let size = fs::metadata(&dll_info.path).ok().map(|m| m.len());
+
+    Ok(CachedArtifact {
+        url: dll_info.url.clone(),
+        name: dll_info.name.clone(),
+        has_wasm_module_cache: dll_info.module_cache_path(false).exists()
+            || dll_info.module_cache_path(true).exists(),
+        last_access: last_access_of(&dll_info.path),
+        path: dll_info.path,
+        size,
+    })
+}
+
+fn inspect_platform(
+    platform: &str,
+    p_manifest: &PlatformManifest,
+    work_dir: &PathBuf,
+) -> Result<CachedPlatform> {
+    let mut artifacts = vec![inspect_artifact(&p_manifest.lib_spec(), work_dir)?];
+    let mut dependencies = Vec::new();
+
+    for dep in &p_manifest.dependencies {
+        match dep {
+            Dependency::RawLib { .. } => {
+                artifacts.push(inspect_artifact(&dep.lib_spec().unwrap(), work_dir)?);
+            }
+            Dependency::DllPack { url, .. } => dependencies.push(url.clone()),
+        }
+    }
+
+    Ok(CachedPlatform {
+        platform: platform.to_string(),
+        artifacts,
+        dependencies,
+    })
+}
+
+/// Lists the cache keys and the recorded URLs of all manifests stored in the cache.
+fn cached_manifests(work_dir: &PathBuf) -> Result<Vec<(String, Url)>> {
+    let manifests_dir = work_dir.join(cache::MANIFESTS_DIR);
+    if !manifests_dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut manifests = Vec::new();
+
+    for entry in fs::read_dir(&manifests_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name();
+        let Some(key) = file_name.to_str() else {
+            continue;
+        };
+
+        match cache::url_of_key(work_dir, key) {
+            Ok(Some(url)) => manifests.push((key.to_string(), url)),
+            Ok(None) => debug!("skipping manifest cache entry without index: {}", key),
+            Err(e) => warn!(
+                "skipping manifest cache entry with a broken index {}: {}",
+                key, e
+            ),
+        }
+    }
+
+    Ok(manifests)
+}
+
+/// Lists every dllpack stored in `work_dir`, together with its platforms,
+/// artifacts, their sizes and the last time they were accessed.
+///
+/// This does not trigger any downloads.
+/// Artifacts that are referenced by a manifest but not downloaded have `size == None`.
+/// Cache entries that cannot be read, such as corrupt manifests, are skipped with a warning.
+///
+/// The URLs of the listed dllpacks are the ones recorded in the cache,
+/// without credentials and query strings.
+pub fn list_cached_packs(work_dir: &Path) -> Result<Vec<CachedPack>> {
+    cache::prepare(work_dir)?;
+    let work_dir = &cache::current_generation(work_dir)?;
+
+    let mut files = BTreeMap::new();
+
+    for (key, url) in cached_manifests(work_dir)? {
+        let info = ManifestInfo::from_key(&url, &key, work_dir);
+        match info.read_file() {
+            Ok(file) => {
+                files.insert(key, (info, file));
+            }
+            Err(e) => warn!("skipping the cached dllpack file {}: {}", url, e),
+        }
+    }
+
+    let mut depended_on = BTreeSet::new();
+    for (_, file) in files.values() {
+        for p_manifest in file.manifest.platforms.values() {
+            for dep in &p_manifest.dependencies {
+                if let Dependency::DllPack { url, .. } = dep {
+                    depended_on.insert(cache::url_key(url));
+                }
+            }
+        }
+    }
+
+    let mut result = Vec::new();
+
+    for (key, (info, file)) in files {
+        let url = info.url.clone();
+        let platforms: Result<Vec<_>> = file
+            .manifest
+            .platforms
+            .iter()
+            .map(|(platform, p_manifest)| inspect_platform(platform, p_manifest, work_dir))
+            .collect();
+        let platforms = match platforms {
+            Ok(platforms) => platforms,
+            Err(e) => {
+                warn!("skipping the cached dllpack file {}: {}", url, e);
+                continue;
+            }
+        };
+
+        let last_access = platforms
+            .iter()
+            .flat_map(|p| p.artifacts.iter())
+            .filter_map(|a| a.last_access)
+            .chain(last_access_of(&info.path))
+            .max();
+
+        result.push(CachedPack {
+            is_root: !depended_on.contains(&key),
+            url,
+            manifest_path: info.path,
+            metadata: file.metadata,
+            platforms,
+            last_access,
+        });
+    }
+
+    result.sort_by(|a, b| a.url.cmp(&b.url));
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn write_cached_manifest(work_dir: &PathBuf, url: &str, content: &str) {
+        let info = ManifestInfo::from_input(&Url::from_str(url).unwrap(), work_dir).unwrap();
+        crate::download::write_manifest(&info, content.as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_list_cached_packs() {
+        let work_dir =
+            std::env::temp_dir().join(format!("dll-pack-inspect-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&work_dir);
+
+        write_cached_manifest(
+            &work_dir,
+            "https://example.com/app.dllpack?token=secret",
+            r#"{
+                "spec-version": "1.0.0",
+                "metadata": { "name": "app" },
+                "manifest": { "platforms": { "wasm32-wasip1": {
+                    "url": "app.wasm",
+                    "dependencies": [{ "type": "dllpack", "url": "adder.dllpack" }]
+                } } }
+            }"#,
+        );
+        write_cached_manifest(
+            &work_dir,
+            "https://example.com/adder.dllpack",
+            r#"{
+                "spec-version": "1.0.0",
+                "manifest": { "platforms": { "wasm32-wasip1": { "url": "adder.wasm" } } }
+            }"#,
+        );
+        write_cached_manifest(&work_dir, "https://example.com/broken.dllpack", "{");
+
+        // Only the library of the app has been downloaded.
+        let app_lib = Url::from_str("https://example.com/app.wasm").unwrap();
+        let app_lib_path = work_dir.join(cache::url_key(&app_lib)).join("app.wasm");
+        fs::create_dir_all(app_lib_path.parent().unwrap()).unwrap();
+        fs::write(&app_lib_path, b"\0asm").unwrap();
+
+        let packs = list_cached_packs(&work_dir).unwrap();
+        let urls: Vec<_> = packs.iter().map(|p| p.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            [
+                "https://example.com/adder.dllpack",
+                "https://example.com/app.dllpack"
+            ]
+        );
+
+        let (adder, app) = (&packs[0], &packs[1]);
+        assert!(app.is_root);
+        assert!(!adder.is_root);
+        assert_eq!(app.metadata.as_ref().unwrap().name.as_deref(), Some("app"));
+        assert_eq!(app.artifacts_size(), 4);
+        assert_eq!(adder.platforms[0].artifacts[0].size, None);
+        assert_eq!(
+            app.platforms[0].dependencies,
+            [Url::from_str("https://example.com/adder.dllpack").unwrap()]
+        );
+
+        fs::remove_dir_all(&work_dir).unwrap();
+    }
+}
@@ -15,21 +15,42 @@ use std::path::PathBuf;
 use url::Url;
 use wasmtime::IntoFunc;
 // Public modules that comprise the main API
+pub mod advisory; // Advisories that block known-bad dllpacks
+pub mod bundle; // Single-file bundles of dllpacks
+mod cache; // Internal on-disk cache layout
+pub mod channel; // Release channels pointing to concrete dllpacks
+pub mod component; // WebAssembly components (wasip2)
+pub mod compression; // Compression formats of published libraries
 pub mod dependency; // Dependency management and resolution
 pub mod dllpack_file; // DLLPack file format handling
 mod download; // Internal module for downloading libraries
+pub mod fetch_policy; // Restrictions on fetched URLs and redirects
 mod fs_utils; // Internal file system utilities
+mod glob; // Internal glob pattern matching
+pub mod inspect; // Inspection of the local cache contents
 pub mod load; // Core library loading functionality
 pub mod process_cache_multi; // Multiprocess caching of loaded libraries
 pub mod process_cache_single; // Process-level caching of loaded libraries
+pub mod registry; // Registry indexes for loading packages by name
 pub mod resolve; // Dependency resolution logic
-mod type_utils;
-// Internal type utilities and helpers
+pub mod sbom; // Software bills of materials of resolved graphs
+#[cfg(test)]
+mod test_fixtures; // Dllpacks on disk shared by the tests of several modules
+mod type_utils; // Internal type utilities and helpers
+pub mod update; // Detection and application of updates of cached dllpacks
+pub mod vendor; // Mirroring dllpacks into self-contained directories
+pub mod wasi_config; // WASI environments of wasm libraries
+mod wasm_cache; // Shared wasm engine and compiled module caches
 
 // Re-export commonly used types and functions for convenience
-pub use load::{load, load_with_platform, load_with_wasm, Function, Library};
+pub use cache::{CacheLayer, CacheLayers, CacheLocation};
+pub use load::{
+    load, load_with_platform, load_with_wasm, load_with_wasm_options, CallError, CallOutput,
+    Function, Library, WasmLimits, WasmOptions, WasmState,
+};
 pub use process_cache_multi::{run_multi_cached, run_multi_cached_with_platform};
 pub use process_cache_single::{run_single_cached, run_single_cached_with_platform};
+pub use registry::{load_by_name, Registry};
 
 #[cfg(test)]
 mod tests {
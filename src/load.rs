@@ -1,6 +1,12 @@
-use crate::fs_utils::get_available_drives;
-use crate::resolve::{resolve, ResolveError};
+use crate::cache::CacheLocation;
+use crate::channel::resolve_channel;
+use crate::component::ComponentLibrary;
+use crate::dllpack_file::Metadata;
+use crate::download::{DllInfo, ManifestInfo};
+use crate::resolve::{resolve_concrete, ResolveError};
 use crate::type_utils::{Caller, IOToFn};
+use crate::wasi_config::{Captures, WasiConfig};
+use crate::wasm_cache;
 use anyhow::{anyhow, Result};
 #[cfg(unix)]
 use libloading::os::unix::{
@@ -16,14 +22,18 @@ use libloading::os::windows::{
 };
 use log::{debug, info, trace};
 use std::fmt::format;
-use std::fs;
+use std::fmt::Display;
 use std::ops::Deref;
 use std::path::PathBuf;
 use std::str::FromStr;
+use std::time::Duration;
 use url::Url;
-use wasmtime::{Config, Engine, Instance as WasmInstance, Linker, Module, Store, TypedFunc};
+use wasmtime::{
+    Instance as WasmInstance, ResourceLimiter, Store, StoreLimits, StoreLimitsBuilder, Trap,
+    TypedFunc, WasmBacktrace,
+};
 use wasmtime_wasi::preview1::WasiP1Ctx;
-use wasmtime_wasi::{preview1, DirPerms, FilePerms, WasiCtxBuilder};
+use wasmtime_wasi::{I32Exit, ResourceTable, WasiCtx, WasiCtxBuilder, WasiView};
 
 /// It represents a callable function loaded from a library,
 /// abstracting both native and WASM libraries.
@@ -38,6 +48,124 @@ where
     WasmFunction(TypedFunc<Args, Res>),
 }
 
+/// A frame of the guest call stack at the point of a wasm trap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WasmFrame {
+    pub func_index: u32,
+    /// The name of the function, from the name section of the module.
+    pub func_name: Option<String>,
+    /// The name of the module, from the name section of the module.
+    pub module_name: Option<String>,
+}
+
+impl Display for WasmFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(module_name) = &self.module_name {
+            write!(f, "{}!", module_name)?;
+        }
+        match &self.func_name {
+            Some(name) => write!(f, "{}", name),
+            None => write!(f, "<wasm function {}>", self.func_index),
+        }
+    }
+}
+
+/// An error of [`Function::try_call`].
+#[derive(Debug)]
+pub enum CallError {
+    /// The wasm function trapped.
+    Trap {
+        code: Trap,
+        /// The guest call stack, innermost frame first.
+        backtrace: Vec<WasmFrame>,
+    },
+    /// The guest called WASI `proc_exit` with the given status.
+    Exit(i32),
+    /// The wasm function did not return within its timeout.
+    /// The library is unusable afterwards, see [`Library::is_poisoned`].
+    Timeout(Duration),
+    /// The wasm function ran out of fuel.
+    /// The library is unusable afterwards, see [`Library::is_poisoned`].
+    OutOfFuel,
+    /// The library is unusable, as an earlier call was interrupted
+    /// (or, for a component, failed).
+    Poisoned,
+    /// A wasm function was called with a native library,
+    /// or a function of a component with another kind of library.
+    LibraryMismatch,
+    /// Any other error from the wasm runtime, such as a failed host function.
+    Other(anyhow::Error),
+}
+
+impl CallError {
+    fn from_wasm_error(e: anyhow::Error) -> Self {
+        if let Some(exit) = e.downcast_ref::<I32Exit>() {
+            return CallError::Exit(exit.0);
+        }
+
+        let Some(code) = e.downcast_ref::<Trap>().copied() else {
+            return CallError::Other(e);
+        };
+
+        let backtrace = e
+            .downcast_ref::<WasmBacktrace>()
+            .map(|bt| {
+                bt.frames()
+                    .iter()
+                    .map(|frame| WasmFrame {
+                        func_index: frame.func_index(),
+                        func_name: frame.func_name().map(str::to_string),
+                        module_name: frame.module().name().map(str::to_string),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        CallError::Trap { code, backtrace }
+    }
+}
+
+impl Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::Trap { code, backtrace } => {
+                write!(f, "{}", code)?;
+                for (i, frame) in backtrace.iter().enumerate() {
+                    write!(f, "\n  {}: {}", i, frame)?;
+                }
+                Ok(())
+            }
+            CallError::Exit(status) => write!(f, "Wasm guest exited with status {}", status),
+            CallError::Timeout(timeout) => write!(f, "Wasm call timed out after {:?}", timeout),
+            CallError::OutOfFuel => write!(f, "Wasm call ran out of fuel"),
+            CallError::Poisoned => {
+                write!(
+                    f,
+                    "Wasm library is unusable, as an earlier call was interrupted or failed"
+                )
+            }
+            CallError::LibraryMismatch => {
+                write!(f, "Wasm function cannot be called without Wasm library")
+            }
+            CallError::Other(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/// The result of [`Function::try_call_captured`], with the output of the call.
+/// The output is empty unless the library captures it, see
+/// [`OutputStream::Capture`](crate::wasi_config::OutputStream::Capture).
+#[derive(Debug)]
+pub struct CallOutput<Res> {
+    pub result: Result<Res, CallError>,
+    /// The standard output of the call.
+    pub stdout: Vec<u8>,
+    /// The standard error of the call.
+    pub stderr: Vec<u8>,
+}
+
 impl<Args, Res> Function<Args, Res>
 where
     Args: wasmtime::WasmParams,
@@ -46,20 +174,159 @@ where
     Args: Caller<Args, Res>,
 {
     /// Dispatches function calls to either native or wasm implementations based on the variant.
-    pub fn call(&self, library: &mut Library, args: Args) -> Res {
+    ///
+    /// Errors of wasm functions, such as traps, are returned as a [`CallError`].
+    /// Wasm functions are interrupted after the timeout of the library, if it has one
+    /// (see [`Library::set_timeout`]).
+    /// Native functions cannot fail this way.
+    pub fn try_call(&self, library: &mut Library, args: Args) -> Result<Res, CallError> {
+        self.try_call_inner(library, args, None)
+    }
+
+    /// Like [`try_call`](Self::try_call), but wasm functions are interrupted after `timeout`
+    /// instead of the timeout of the library.
+    pub fn try_call_with_timeout(
+        &self,
+        library: &mut Library,
+        args: Args,
+        timeout: Duration,
+    ) -> Result<Res, CallError> {
+        self.try_call_inner(library, args, Some(timeout))
+    }
+
+    /// Like [`try_call`](Self::try_call), and returns the standard output and error
+    /// written during the call, if the library captures them
+    /// (see [`OutputStream::Capture`](crate::wasi_config::OutputStream::Capture)).
+    pub fn try_call_captured(&self, library: &mut Library, args: Args) -> CallOutput<Res> {
+        let result = self.try_call_inner(library, args, None);
+        let (stdout, stderr) = library.take_captured_output();
+
+        CallOutput {
+            result,
+            stdout,
+            stderr,
+        }
+    }
+
+    fn try_call_inner(
+        &self,
+        library: &mut Library,
+        args: Args,
+        timeout: Option<Duration>,
+    ) -> Result<Res, CallError> {
         match &self {
             Function::LLFunction(symbol) => unsafe {
                 let a = symbol.deref();
-                <Args as Caller<Args, Res>>::call(args, a)
+                Ok(<Args as Caller<Args, Res>>::call(args, a))
             },
             Function::WasmFunction(func) => {
-                let Library::WasmLibrary(WasmLibrary { store, .. }) = library else {
-                    panic!("Wasm function cannot be called without Wasm library");
+                let Library::WasmLibrary(lib) = library else {
+                    return Err(CallError::LibraryMismatch);
                 };
-                <TypedFunc<Args, Res>>::call(func, store, args).unwrap()
+
+                lib.runtime.run(timeout, |store| {
+                    <TypedFunc<Args, Res>>::call(func, store, args)
+                })
             }
         }
     }
+
+    /// Like [`try_call`](Self::try_call), but panics on errors.
+    pub fn call(&self, library: &mut Library, args: Args) -> Res {
+        match self.try_call(library, args) {
+            Ok(res) => res,
+            Err(e) => panic!("{}", e),
+        }
+    }
+}
+
+/// The store of a wasm library or component, and the settings and state of calls into it.
+pub struct WasmRuntime {
+    pub store: Store<WasmState>,
+    /// The timeout of calls to functions of the library.
+    pub timeout: Option<Duration>,
+    /// The fuel given to every call, if the library consumes fuel.
+    /// If `None`, calls draw from the fuel of the instance, see [`Library::set_fuel`].
+    pub fuel_per_call: Option<u64>,
+    /// The fuel consumed by the last call, if the library consumes fuel.
+    pub last_fuel_consumed: Option<u64>,
+    /// Whether the instance can no longer be called, see [`Library::is_poisoned`].
+    pub poisoned: bool,
+}
+
+impl WasmRuntime {
+    pub(crate) fn new(store: Store<WasmState>) -> Self {
+        Self {
+            store,
+            timeout: None,
+            fuel_per_call: None,
+            last_fuel_consumed: None,
+            poisoned: false,
+        }
+    }
+
+    /// Runs a call on the store, interrupted after `timeout` or else the timeout of the library,
+    /// and with the fuel settings of the library.
+    /// A call that is interrupted poisons the library.
+    pub(crate) fn run<T>(
+        &mut self,
+        timeout: Option<Duration>,
+        call: impl FnOnce(&mut Store<WasmState>) -> Result<T>,
+    ) -> Result<T, CallError> {
+        if self.poisoned {
+            return Err(CallError::Poisoned);
+        }
+
+        // Captured output is kept for one call only.
+        self.store.data().captures.take_all();
+
+        let timeout = timeout.or(self.timeout);
+        let deadline = match timeout {
+            Some(timeout) => wasm_cache::epoch_deadline(timeout),
+            None => wasm_cache::NO_EPOCH_DEADLINE,
+        };
+        self.store.set_epoch_deadline(deadline);
+
+        // Getting the fuel fails if the library does not consume fuel.
+        if let Some(fuel) = self.fuel_per_call {
+            self.store.set_fuel(fuel).map_err(CallError::Other)?;
+        }
+        let fuel_before = self.store.get_fuel().ok();
+
+        let result = call(&mut self.store);
+
+        if let (Some(before), Ok(after)) = (fuel_before, self.store.get_fuel()) {
+            self.last_fuel_consumed = Some(before - after);
+        }
+
+        match result {
+            Ok(res) => Ok(res),
+            Err(e) => match (CallError::from_wasm_error(e), timeout) {
+                // The guest was stopped at an arbitrary point, so its state may be broken.
+                (
+                    CallError::Trap {
+                        code: Trap::Interrupt,
+                        ..
+                    },
+                    Some(timeout),
+                ) => {
+                    self.poisoned = true;
+                    Err(CallError::Timeout(timeout))
+                }
+                (
+                    CallError::Trap {
+                        code: Trap::OutOfFuel,
+                        ..
+                    },
+                    _,
+                ) => {
+                    self.poisoned = true;
+                    Err(CallError::OutOfFuel)
+                }
+                (e, _) => Err(e),
+            },
+        }
+    }
 }
 
 /// A struct that stores OS-native DLLs.
@@ -70,33 +337,320 @@ where
 pub struct NativeLibrary {
     pub raw_library: LLNativeLibrary,
     pub raw_dependencies: Vec<LLNativeLibrary>,
+    /// The concrete URL of the loaded dllpack, after following channels.
+    pub manifest_url: Url,
+    /// The metadata of the loaded dllpack, if its manifest has any.
+    pub metadata: Option<Metadata>,
+}
+
+/// The data of the store of a wasm library.
+pub struct WasmState {
+    /// The WASI context, if the library is loaded for a WASI platform.
+    pub wasi: Option<WasiP1Ctx>,
+    /// The WASI preview2 context, if the library is a component.
+    pub wasi_p2: Option<WasiCtx>,
+    /// The resources of a component, such as streams.
+    pub table: ResourceTable,
+    /// The limits of the resources of the instance, enforced by the store.
+    pub limits: StoreLimits,
+    pub(crate) captures: Captures,
+    /// The total size in bytes of the linear memories of the store.
+    memory_usage: usize,
+    /// The size of the last memory growth that the limiter allowed, until it is known to have failed.
+    last_memory_growth: usize,
+}
+
+impl WasmState {
+    pub(crate) fn new(limits: &WasmLimits) -> Self {
+        Self {
+            wasi: None,
+            wasi_p2: None,
+            table: ResourceTable::new(),
+            limits: limits.store_limits(),
+            captures: Captures::default(),
+            memory_usage: 0,
+            last_memory_growth: 0,
+        }
+    }
+}
+
+/// Enforces [`WasmState::limits`], and keeps track of the memory usage of the store.
+impl ResourceLimiter for WasmState {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        let allowed = self.limits.memory_growing(current, desired, maximum)?;
+        if allowed {
+            // New memories are reported as growing from 0.
+            self.last_memory_growth = desired.saturating_sub(current);
+            self.memory_usage += self.last_memory_growth;
+        }
+
+        Ok(allowed)
+    }
+
+    fn memory_grow_failed(&mut self, error: anyhow::Error) -> Result<()> {
+        self.memory_usage -= std::mem::take(&mut self.last_memory_growth);
+        self.limits.memory_grow_failed(error)
+    }
+
+    fn table_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        maximum: Option<usize>,
+    ) -> Result<bool> {
+        self.limits.table_growing(current, desired, maximum)
+    }
+
+    fn table_grow_failed(&mut self, error: anyhow::Error) -> Result<()> {
+        self.limits.table_grow_failed(error)
+    }
+
+    fn instances(&self) -> usize {
+        self.limits.instances()
+    }
+
+    fn tables(&self) -> usize {
+        self.limits.tables()
+    }
+
+    fn memories(&self) -> usize {
+        self.limits.memories()
+    }
+}
+
+impl WasiView for WasmState {
+    fn table(&mut self) -> &mut ResourceTable {
+        &mut self.table
+    }
+
+    fn ctx(&mut self) -> &mut WasiCtx {
+        // Only components are linked with preview2, and they always have a context.
+        self.wasi_p2
+            .get_or_insert_with(|| WasiCtxBuilder::new().build())
+    }
 }
 
 /// A struct that encapsulates a wasmtime instance and a context for WASI operations.
 pub struct WasmLibrary {
     pub instance: WasmInstance,
-    pub store: Store<WasiP1Ctx>,
+    pub runtime: WasmRuntime,
+    /// The concrete URL of the loaded dllpack, after following channels.
+    pub manifest_url: Url,
+    /// The metadata of the loaded dllpack, if its manifest has any.
+    pub metadata: Option<Metadata>,
+}
+
+/// Options of wasm libraries, for [`load_with_wasm_options`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasmOptions {
+    /// The WASI environment of the library. It is ignored for platforms without WASI.
+    pub wasi: WasiConfig,
+    /// Whether the library consumes fuel, so that its calls can be given fuel budgets
+    /// and their cost can be read back (see [`Library::set_fuel`]).
+    /// This makes wasm code slower, and is disabled by default.
+    pub consume_fuel: bool,
+    /// The limits of the resources of the instance.
+    pub limits: WasmLimits,
+}
+
+/// Limits of the resources of a wasm instance. Nothing is limited by default.
+///
+/// Growing a memory or a table beyond its limit fails inside the guest
+/// (`memory.grow` returns -1), rather than trapping.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasmLimits {
+    /// The maximum size of each linear memory, in 64 KiB pages.
+    pub max_memory_pages: Option<u64>,
+    /// The maximum number of elements of each table.
+    pub max_table_elements: Option<usize>,
+    /// The maximum number of instances.
+    pub max_instances: Option<usize>,
+    /// The maximum number of linear memories.
+    pub max_memories: Option<usize>,
+    /// The maximum number of tables.
+    pub max_tables: Option<usize>,
+}
+
+/// The size of a page of wasm linear memory.
+const WASM_PAGE_SIZE: u64 = 0x10000;
+
+impl WasmLimits {
+    fn store_limits(&self) -> StoreLimits {
+        let mut builder = StoreLimitsBuilder::new();
+
+        if let Some(pages) = self.max_memory_pages {
+            let bytes = pages.saturating_mul(WASM_PAGE_SIZE);
+            builder = builder.memory_size(usize::try_from(bytes).unwrap_or(usize::MAX));
+        }
+        if let Some(elements) = self.max_table_elements {
+            builder = builder.table_elements(elements);
+        }
+        if let Some(instances) = self.max_instances {
+            builder = builder.instances(instances);
+        }
+        if let Some(memories) = self.max_memories {
+            builder = builder.memories(memories);
+        }
+        if let Some(tables) = self.max_tables {
+            builder = builder.tables(tables);
+        }
+
+        builder.build()
+    }
 }
 
 /// An interface that abstracts both native libraries and WASM libraries.
 pub enum Library {
     NativeLibrary(NativeLibrary),
     WasmLibrary(WasmLibrary),
+    ComponentLibrary(ComponentLibrary),
 }
 
 impl Library {
     pub(crate) fn new_native_library(
         raw_library: LLNativeLibrary,
         raw_dependencies: Vec<LLNativeLibrary>,
+        manifest_url: Url,
+        metadata: Option<Metadata>,
     ) -> Self {
         Library::NativeLibrary(NativeLibrary {
             raw_library,
             raw_dependencies,
+            manifest_url,
+            metadata,
+        })
+    }
+
+    pub(crate) fn new_wasm_library(
+        instance: WasmInstance,
+        store: Store<WasmState>,
+        manifest_url: Url,
+        metadata: Option<Metadata>,
+    ) -> Self {
+        Library::WasmLibrary(WasmLibrary {
+            instance,
+            runtime: WasmRuntime::new(store),
+            manifest_url,
+            metadata,
         })
     }
 
-    pub(crate) fn new_wasm_library(instance: WasmInstance, store: Store<WasiP1Ctx>) -> Self {
-        Library::WasmLibrary(WasmLibrary { instance, store })
+    /// The concrete URL of the loaded dllpack.
+    /// It differs from the URL passed to `load` if that URL is a [channel](crate::channel).
+    pub fn manifest_url(&self) -> &Url {
+        match self {
+            Library::NativeLibrary(lib) => &lib.manifest_url,
+            Library::WasmLibrary(WasmLibrary { manifest_url, .. })
+            | Library::ComponentLibrary(ComponentLibrary { manifest_url, .. }) => manifest_url,
+        }
+    }
+
+    /// The metadata (name, version, license, ...) of the loaded dllpack, if its manifest has any.
+    pub fn metadata(&self) -> Option<&Metadata> {
+        match self {
+            Library::NativeLibrary(lib) => lib.metadata.as_ref(),
+            Library::WasmLibrary(WasmLibrary { metadata, .. })
+            | Library::ComponentLibrary(ComponentLibrary { metadata, .. }) => metadata.as_ref(),
+        }
+    }
+
+    /// The store and call state of a wasm library or component, or `None` for native libraries.
+    fn runtime(&self) -> Option<&WasmRuntime> {
+        match self {
+            Library::NativeLibrary(_) => None,
+            Library::WasmLibrary(WasmLibrary { runtime, .. })
+            | Library::ComponentLibrary(ComponentLibrary { runtime, .. }) => Some(runtime),
+        }
+    }
+
+    fn runtime_mut(&mut self) -> Option<&mut WasmRuntime> {
+        match self {
+            Library::NativeLibrary(_) => None,
+            Library::WasmLibrary(WasmLibrary { runtime, .. })
+            | Library::ComponentLibrary(ComponentLibrary { runtime, .. }) => Some(runtime),
+        }
+    }
+
+    /// Sets the timeout of calls to functions of this library, or removes it with `None`.
+    /// A call that times out returns [`CallError::Timeout`], and makes the library unusable.
+    ///
+    /// Only calls to wasm libraries can be interrupted, so this fails for native libraries.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        let runtime = self
+            .runtime_mut()
+            .ok_or(anyhow!("Native library calls cannot time out"))?;
+        runtime.timeout = timeout;
+
+        Ok(())
+    }
+
+    /// Sets the fuel of the instance, which all calls draw from.
+    /// A call that runs out of fuel returns [`CallError::OutOfFuel`], and makes the library unusable.
+    ///
+    /// It fails unless the library was loaded with [`WasmOptions::consume_fuel`].
+    /// The fuel is unlimited until this is called.
+    pub fn set_fuel(&mut self, fuel: u64) -> Result<()> {
+        self.runtime_mut()
+            .ok_or(anyhow!("Native libraries do not consume fuel"))?
+            .store
+            .set_fuel(fuel)
+    }
+
+    /// Gives every call `fuel`, independently of earlier calls, or removes the budget with `None`.
+    ///
+    /// Removing the budget also resets the fuel of the instance to unlimited,
+    /// as it holds what the last call left over; use [`Library::set_fuel`] to limit it again.
+    ///
+    /// It fails unless the library was loaded with [`WasmOptions::consume_fuel`].
+    pub fn set_fuel_per_call(&mut self, fuel: Option<u64>) -> Result<()> {
+        let runtime = self
+            .runtime_mut()
+            .ok_or(anyhow!("Native libraries do not consume fuel"))?;
+
+        runtime.store.get_fuel()?;
+        runtime.fuel_per_call = fuel;
+        if fuel.is_none() {
+            runtime.store.set_fuel(u64::MAX)?;
+        }
+
+        Ok(())
+    }
+
+    /// The fuel left to the instance, if the library consumes fuel.
+    pub fn fuel_remaining(&self) -> Option<u64> {
+        self.runtime()?.store.get_fuel().ok()
+    }
+
+    /// The fuel consumed by the last call, if the library consumes fuel.
+    /// It is also recorded for calls that fail.
+    pub fn last_fuel_consumed(&self) -> Option<u64> {
+        self.runtime()?.last_fuel_consumed
+    }
+
+    /// The total size in bytes of the linear memories of the instance,
+    /// including the ones it does not export, or `None` for native libraries.
+    pub fn memory_usage(&self) -> Option<usize> {
+        Some(self.runtime()?.store.data().memory_usage)
+    }
+
+    /// Takes the standard output and error captured during the last call.
+    pub(crate) fn take_captured_output(&self) -> (Vec<u8>, Vec<u8>) {
+        match self.runtime() {
+            Some(runtime) => runtime.store.data().captures.take_all(),
+            None => Default::default(),
+        }
+    }
+
+    /// Whether the library is unusable, as a call has been interrupted
+    /// (or, for a component, has failed).
+    /// The process caches discard such libraries.
+    pub fn is_poisoned(&self) -> bool {
+        self.runtime().is_some_and(|runtime| runtime.poisoned)
     }
 
     /// Retrieves a function from the library with type-safe bindings.
@@ -115,14 +669,28 @@ impl Library {
                     unsafe { lib.get(name.as_bytes())? };
                 Ok(Function::LLFunction(symbol))
             }
-            Library::WasmLibrary(WasmLibrary { instance, store }) => {
-                let func = instance.get_typed_func::<Args, Res>(store, name)?;
+            Library::WasmLibrary(WasmLibrary {
+                instance, runtime, ..
+            }) => {
+                let func = instance.get_typed_func::<Args, Res>(&mut runtime.store, name)?;
                 Ok(Function::WasmFunction(func))
             }
+            Library::ComponentLibrary(_) => Err(anyhow!(
+                "Functions of components are retrieved with `get_component_function`"
+            )),
         }
     }
 }
 
+/// Reads the metadata of a dllpack that has already been resolved, and is therefore cached.
+fn cached_metadata(url: &Url, work_dir: &impl CacheLocation) -> Result<Option<Metadata>> {
+    let layers = work_dir.cache_layers().prepare()?;
+
+    Ok(ManifestInfo::from_layers(url, &layers)?
+        .read_file()?
+        .metadata)
+}
+
 fn is_wasm(platform: &str) -> bool {
     platform.contains("wasm")
 }
@@ -131,34 +699,45 @@ fn is_wasi(platform: &str) -> bool {
     platform.contains("wasi")
 }
 
-#[cfg(unix)]
-fn pre_open_all(wasi_ctx_builder: &mut WasiCtxBuilder) -> Result<()> {
-    wasi_ctx_builder.preopened_dir("/", "/", DirPerms::all(), FilePerms::all())?;
-
-    Ok(())
+fn is_component(platform: &str) -> bool {
+    platform.contains("wasip2")
 }
 
-#[cfg(windows)]
-fn pre_open_all(wasi_ctx_builder: &mut WasiCtxBuilder) -> Result<()> {
-    // Note that it cannot handle, for example, drives connected after the context has been created.
-    // Some alternative solution is needed for this.
-    for drive in get_available_drives() {
-        wasi_ctx_builder.preopened_dir(
-            format!("{}:\\", drive.to_uppercase()),
-            format!("/{}", drive.to_lowercase()),
-            DirPerms::all(),
-            FilePerms::all(),
-        )?;
-    }
+/// Loads a wasm library with WASI support.
+///
+/// WASI is linked if the platform is a WASI one (such as `wasm32-wasip1`).
+/// Otherwise (such as `wasm32-unknown-unknown`), the module is instantiated without WASI,
+/// and must not import anything.
+/// For `wasm32-wasip2`, the library is a component, linked with WASI preview2
+/// (see [`crate::component`]).
+///
+/// The module is compiled once per process and shared by every load of the same library;
+/// each load gets its own instance and WASI context.
+pub fn load_with_wasm(url: &Url, work_dir: &impl CacheLocation, platform: &str) -> Result<Library> {
+    load_with_wasm_options(url, work_dir, platform, &WasmOptions::default())
+}
 
-    Ok(())
+/// Like [`load_with_wasm`], with options of the wasm library.
+pub fn load_with_wasm_options(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    platform: &str,
+    options: &WasmOptions,
+) -> Result<Library> {
+    load_following_channels(url, work_dir, Some(platform), options)
 }
 
-/// Loads a wasm library with WASI support, including module caching for performance.
-pub fn load_with_wasm(url: &Url, work_dir: &PathBuf, platform: &str) -> Result<Library> {
+/// Loads the wasm library of the concrete dllpack at `url`.
+fn load_wasm(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    platform: &str,
+    options: &WasmOptions,
+) -> Result<Library> {
     debug!("toplevel-load with {}: {}", platform, url);
 
-    let (base_info, dependency_load_order_paths) = resolve(url, work_dir, platform)?;
+    let layers = work_dir.cache_layers().prepare()?;
+    let (base_info, dependency_load_order_paths) = resolve_concrete(url, &layers, platform)?;
 
     // Basic wasm file cannot include dependencies.
     // Note: Wasm component can include dependencies maybe.
@@ -166,75 +745,65 @@ pub fn load_with_wasm(url: &Url, work_dir: &PathBuf, platform: &str) -> Result<L
         return Err(anyhow!("Wasm file cannot include dependencies"));
     }
 
-    let mut config = Config::default();
-    // See https://github.com/bytecodealliance/wasmtime/issues/8897
-    #[cfg(unix)]
-    config.native_unwind_info(false);
-
-    let engine = Engine::new(&config)?;
-
-    let cache_path = base_info.wasm_module_cache_path();
-
-    // Use cached module if available.
-    let module = if cache_path.exists() {
-        debug!(
-            "{}: loading from cache: {}",
-            base_info.name,
-            cache_path.display()
-        );
-
-        let module;
-        unsafe {
-            module = Module::deserialize_file(&engine, &cache_path)?;
-        }
-
-        module
-    } else {
-        debug!(
-            "{}: manual loading: {}",
-            base_info.name,
-            base_info.path.display()
-        );
-
-        let wasm_bin = fs::read(&base_info.path)?;
-        let module = Module::from_binary(&engine, wasm_bin.as_slice())?;
-
-        let cache_bin = module.serialize()?;
-
-        trace!("serializing to cache: {}", cache_path.display());
-
-        fs::create_dir_all(cache_path.parent().unwrap())?;
-        fs::write(&cache_path, cache_bin)?;
-
-        module
-    };
+    if is_component(platform) {
+        return load_component(url, work_dir, &base_info, options);
+    }
 
-    let mut linker = Linker::new(&engine);
+    // Plain wasm modules (such as `wasm32-unknown-unknown`) are loaded without WASI.
+    let wasi = is_wasi(platform);
+    let pre = wasm_cache::instance_pre(&base_info, options.consume_fuel, wasi)?;
 
-    // Set up wasi environment with full system access.
-    //
-    // One possible way to ensure security would be
-    // to limit the host-side permissions accessible from the WASI environment.
-    // However, since dllpack can load native libraries,
-    // such restrictions would not be very meaningful in practice.
-    //
-    // Therefore, we do not plan to offer such an option.
-    preview1::add_to_linker_sync(&mut linker, |t| t)?;
-    let pre = linker.instantiate_pre(&module)?;
+    let mut state = WasmState::new(&options.limits);
+    if wasi {
+        let (wasi_ctx, captures) = options.wasi.build_p1(url)?;
+        state.wasi = Some(wasi_ctx);
+        state.captures = captures;
+    }
 
-    let mut wasi_ctx_builder = WasiCtxBuilder::new();
+    let mut store = Store::new(pre.module().engine(), state);
+    store.limiter(|state| state);
+    store.set_epoch_deadline(wasm_cache::NO_EPOCH_DEADLINE);
+    if options.consume_fuel {
+        store.set_fuel(u64::MAX)?;
+    }
+    let instance = pre.instantiate(&mut store)?;
 
-    wasi_ctx_builder.inherit_env();
-    wasi_ctx_builder.inherit_stdio();
+    Ok(Library::new_wasm_library(
+        instance,
+        store,
+        url.clone(),
+        cached_metadata(url, work_dir)?,
+    ))
+}
 
-    pre_open_all(&mut wasi_ctx_builder)?;
+/// Loads a component whose dllpack has been resolved to `base_info`.
+fn load_component(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    base_info: &DllInfo,
+    options: &WasmOptions,
+) -> Result<Library> {
+    let pre = wasm_cache::component_instance_pre(base_info, options.consume_fuel)?;
 
-    let wasi_ctx = wasi_ctx_builder.build_p1();
+    let mut state = WasmState::new(&options.limits);
+    let (wasi_ctx, captures) = options.wasi.build_p2(url)?;
+    state.wasi_p2 = Some(wasi_ctx);
+    state.captures = captures;
 
-    let mut store = Store::new(&engine, wasi_ctx);
+    let mut store = Store::new(pre.engine(), state);
+    store.limiter(|state| state);
+    store.set_epoch_deadline(wasm_cache::NO_EPOCH_DEADLINE);
+    if options.consume_fuel {
+        store.set_fuel(u64::MAX)?;
+    }
     let instance = pre.instantiate(&mut store)?;
 
-    Ok(Library::new_wasm_library(instance, store))
+    Ok(Library::ComponentLibrary(ComponentLibrary::new(
+        instance,
+        store,
+        url.clone(),
+        cached_metadata(url, work_dir)?,
+    )))
 }
 
 #[cfg(unix)]
@@ -249,14 +818,20 @@ unsafe fn libloading_load(path: &PathBuf) -> Result<LLNativeLibrary> {
 
 /// Downloads the dllpack from the specified URL and loads it for the specified platform.
 /// Both the download and loading processes are cached.
-pub fn load_with_platform(url: &Url, work_dir: &PathBuf, platform: &str) -> Result<Library> {
-    if is_wasm(platform) {
-        return load_with_wasm(url, work_dir, platform);
-    }
+pub fn load_with_platform(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    platform: &str,
+) -> Result<Library> {
+    load_following_channels(url, work_dir, Some(platform), &WasmOptions::default())
+}
 
+/// Loads the native library of the concrete dllpack at `url`.
+fn load_native(url: &Url, work_dir: &impl CacheLocation, platform: &str) -> Result<Library> {
     debug!("toplevel-load with {}: {}", platform, url);
 
-    let (base_info, dependency_load_order_paths) = resolve(url, work_dir, platform)?;
+    let layers = work_dir.cache_layers().prepare()?;
+    let (base_info, dependency_load_order_paths) = resolve_concrete(url, &layers, platform)?;
     let mut dependency_libs = Vec::new();
 
     // Load dependencies in order before the main library.
@@ -269,23 +844,52 @@ pub fn load_with_platform(url: &Url, work_dir: &PathBuf, platform: &str) -> Resu
     trace!("loading base library: {}", base_info.url);
     let lib = unsafe { libloading_load(&base_info.path)? };
 
-    Ok(Library::new_native_library(lib, dependency_libs))
+    Ok(Library::new_native_library(
+        lib,
+        dependency_libs,
+        url.clone(),
+        cached_metadata(url, work_dir)?,
+    ))
 }
 
 /// The entry point for library loading that first attempts native loading
 /// and falls back to WASM if necessary.
 /// This provides transparent cross-platform support with WASM as a fallback.
-pub fn load(url: &Url, work_dir: &PathBuf) -> Result<Library> {
-    let this_platform = env!("TARGET_TRIPLE");
-    let with_this_platform = load_with_platform(url, work_dir, this_platform);
+pub fn load(url: &Url, work_dir: &impl CacheLocation) -> Result<Library> {
+    load_following_channels(url, work_dir, None, &WasmOptions::default())
+}
 
-    let res = match with_this_platform {
+/// Follows the channels starting from `url` once, and loads the concrete dllpack for `platform`.
+/// Without a platform, this platform is tried first, falling back to `wasm32-wasip1`.
+fn load_following_channels(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    platform: Option<&str>,
+    options: &WasmOptions,
+) -> Result<Library> {
+    // The whole load reads one generation of the cache, even if an update is published meanwhile.
+    let work_dir = &work_dir.cache_layers().prepare()?;
+    let url = &resolve_channel(url, work_dir)?;
+
+    let load_for = |platform: &str| {
+        if is_wasm(platform) {
+            load_wasm(url, work_dir, platform, options)
+        } else {
+            load_native(url, work_dir, platform)
+        }
+    };
+
+    if let Some(platform) = platform {
+        return load_for(platform);
+    }
+
+    let res = match load_for(env!("TARGET_TRIPLE")) {
         Ok(v) => v,
         Err(e) => {
-            if let Some(m) = e.downcast_ref::<ResolveError>() {
+            if let Some(m @ ResolveError::PlatformNotSupported(_)) = e.downcast_ref() {
                 debug!("Failed to load with this platform: {}", m);
 
-                load_with_wasm(url, work_dir, "wasm32-wasip1")?
+                load_for("wasm32-wasip1")?
             } else {
                 return Err(e);
             }
@@ -296,3 +900,178 @@ pub fn load(url: &Url, work_dir: &PathBuf) -> Result<Library> {
 
     Ok(res)
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::Path;
+
+    /// A module that exports `f: () -> ()`, which executes `unreachable`.
+    const UNREACHABLE_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // export section: "f"
+        0x0a, 0x05, 0x01, 0x03, 0x00, 0x00, 0x0b, // code section: unreachable
+    ];
+
+    /// A module that exports `f: () -> ()`, which loops forever.
+    const LOOP_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x07, 0x05, 0x01, 0x01, b'f', 0x00, 0x00, // export section: "f"
+        0x0a, 0x09, 0x01, 0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b,
+        0x0b, // code section: loop br 0
+    ];
+
+    /// A module that exports a memory of 1 page, and `grow: (i32) -> i32`, which grows it.
+    const GROW_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x06, 0x01, 0x60, 0x01, 0x7f, 0x01, 0x7f, // type section: (i32) -> i32
+        0x03, 0x02, 0x01, 0x00, // function section
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section: 1 page
+        0x07, 0x11, 0x02, // export section
+        0x04, b'g', b'r', b'o', b'w', 0x00, 0x00, // "grow"
+        0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, // "memory"
+        0x0a, 0x08, 0x01, 0x06, 0x00, 0x20, 0x00, 0x40, 0x00,
+        0x0b, // code section: memory.grow
+    ];
+
+    /// Writes `wasm` as the dllpack `name` for `platform` into `dir`, and returns its URL.
+    fn write_test_dllpack(dir: &Path, name: &str, wasm: &[u8], platform: &str) -> Url {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+
+        fs::write(dir.join(format!("{}.wasm", name)), wasm).unwrap();
+        fs::write(
+            dir.join(format!("{}.dllpack", name)),
+            format!(
+                r#"{{
+                    "spec-version": "1.0.0",
+                    "manifest": {{ "platforms": {{ "{}": {{ "url": "{}.wasm" }} }} }}
+                }}"#,
+                platform, name
+            ),
+        )
+        .unwrap();
+
+        Url::from_file_path(dir.join(format!("{}.dllpack", name))).unwrap()
+    }
+
+    /// Loads `wasm` as the dllpack `name` for `platform` through [`load_with_wasm_options`].
+    pub(crate) fn load_test_wasm(
+        name: &str,
+        wasm: &[u8],
+        platform: &str,
+        options: &WasmOptions,
+    ) -> Library {
+        let dir =
+            std::env::temp_dir().join(format!("dll-pack-load-{}-{}", name, std::process::id()));
+        let url = write_test_dllpack(&dir, name, wasm, platform);
+        let library = load_with_wasm_options(&url, &dir.join("work"), platform, options).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+        library
+    }
+
+    #[test]
+    fn test_try_call_trap() {
+        let mut library = load_test_wasm(
+            "trap",
+            UNREACHABLE_WASM,
+            "wasm32-unknown-unknown",
+            &WasmOptions::default(),
+        );
+        let f = library.get_function::<(), ()>("f").unwrap();
+
+        match f.try_call(&mut library, ()) {
+            Err(CallError::Trap { code, backtrace }) => {
+                assert_eq!(code, Trap::UnreachableCodeReached);
+                assert_eq!(backtrace.len(), 1);
+                assert_eq!(backtrace[0].func_index, 0);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_try_call_timeout() {
+        let mut library = load_test_wasm(
+            "timeout",
+            LOOP_WASM,
+            "wasm32-unknown-unknown",
+            &WasmOptions::default(),
+        );
+        let f = library.get_function::<(), ()>("f").unwrap();
+
+        let timeout = Duration::from_millis(50);
+        assert!(matches!(
+            f.try_call_with_timeout(&mut library, (), timeout),
+            Err(CallError::Timeout(t)) if t == timeout
+        ));
+        assert!(library.is_poisoned());
+        assert!(matches!(
+            f.try_call(&mut library, ()),
+            Err(CallError::Poisoned)
+        ));
+    }
+
+    #[test]
+    fn test_out_of_fuel() {
+        let options = WasmOptions {
+            consume_fuel: true,
+            ..Default::default()
+        };
+        let mut library = load_test_wasm("fuel", LOOP_WASM, "wasm32-unknown-unknown", &options);
+        let f = library.get_function::<(), ()>("f").unwrap();
+
+        library.set_fuel(1000).unwrap();
+        assert!(matches!(
+            f.try_call(&mut library, ()),
+            Err(CallError::OutOfFuel)
+        ));
+        assert_eq!(library.last_fuel_consumed(), Some(1000));
+        assert_eq!(library.fuel_remaining(), Some(0));
+        assert!(library.is_poisoned());
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let options = WasmOptions {
+            limits: WasmLimits {
+                max_memory_pages: Some(2),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut library = load_test_wasm("grow", GROW_WASM, "wasm32-unknown-unknown", &options);
+        let grow = library.get_function::<(i32,), i32>("grow").unwrap();
+
+        assert_eq!(library.memory_usage(), Some(0x10000));
+        assert_eq!(grow.call(&mut library, (1,)), 1);
+        assert_eq!(grow.call(&mut library, (1,)), -1);
+        assert_eq!(library.memory_usage(), Some(2 * 0x10000));
+    }
+
+    #[test]
+    fn test_load_without_wasi() {
+        let dir = std::env::temp_dir().join(format!("dll-pack-load-plain-{}", std::process::id()));
+        let url = write_test_dllpack(&dir, "plain", GROW_WASM, "wasm32-unknown-unknown");
+
+        let mut library =
+            load_with_wasm(&url, &dir.join("work"), "wasm32-unknown-unknown").unwrap();
+        let grow = library.get_function::<(i32,), i32>("grow").unwrap();
+        assert_eq!(grow.call(&mut library, (1,)), 1);
+
+        // The store holds no WASI context at all.
+        let Library::WasmLibrary(wasm) = &library else {
+            panic!("expected a wasm library");
+        };
+        assert!(wasm.runtime.store.data().wasi.is_none());
+        assert!(wasm.runtime.store.data().wasi_p2.is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
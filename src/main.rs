@@ -0,0 +1,80 @@
+//! Command line interface of dll-pack.
+
+use anyhow::{anyhow, Result};
+use dll_pack::bundle::bundle;
+use dll_pack::sbom::cyclonedx;
+use dll_pack::vendor::vendor;
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+use url::Url;
+
+const USAGE: &str = "\
+Usage:
+    dll-pack vendor <dllpack-url> <out-dir> [--work-dir <dir>]
+    dll-pack bundle <dllpack-url> <out-file> [--work-dir <dir>]
+    dll-pack sbom <dllpack-url> <platform> [--work-dir <dir>]
+
+Commands:
+    vendor    Mirror a dllpack and its dependencies for all platforms into a directory
+    bundle    Pack a dllpack and its dependencies for all platforms into a single file
+    sbom      Print a CycloneDX SBOM of a dllpack and its dependencies for a platform
+
+Options:
+    --work-dir <dir>    Cache directory used for downloads (default: a directory in the system temp dir)";
+
+/// Parses a URL, accepting plain local paths as well.
+fn parse_url(s: &str) -> Result<Url> {
+    if let Ok(url) = Url::from_str(s) {
+        return Ok(url);
+    }
+
+    let path = std::fs::canonicalize(s)?;
+    Url::from_file_path(&path).map_err(|_| anyhow!("Invalid URL or path: {}", s))
+}
+
+/// Splits `--work-dir <dir>` off the arguments.
+fn take_work_dir(args: &mut Vec<String>) -> Result<PathBuf> {
+    let Some(i) = args.iter().position(|a| a == "--work-dir") else {
+        return Ok(env::temp_dir().join("dll-pack"));
+    };
+
+    if i + 1 >= args.len() {
+        return Err(anyhow!("--work-dir requires a value"));
+    }
+
+    let dir = args.remove(i + 1);
+    args.remove(i);
+
+    Ok(PathBuf::from(dir))
+}
+
+fn run(mut args: Vec<String>) -> Result<()> {
+    let work_dir = take_work_dir(&mut args)?;
+
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        ["vendor", url, out_dir] => {
+            let root = vendor(&parse_url(url)?, &work_dir, &PathBuf::from(out_dir))?;
+            println!("{}", root.display());
+        }
+        ["bundle", url, out_file] => {
+            bundle(&parse_url(url)?, &work_dir, &PathBuf::from(out_file))?;
+        }
+        ["sbom", url, platform] => {
+            let sbom = cyclonedx(&parse_url(url)?, &work_dir, platform)?;
+            println!("{}", serde_json::to_string_pretty(&sbom)?);
+        }
+        _ => return Err(anyhow!("{}", USAGE)),
+    }
+
+    Ok(())
+}
+
+fn main() {
+    if let Err(e) = run(env::args().skip(1).collect()) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
@@ -1,9 +1,9 @@
+use crate::cache::CacheLocation;
 use crate::load::{load_with_platform, Library};
 use crate::resolve::ResolveError;
 use anyhow::Result;
 use log::debug;
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::{Arc, LazyLock, Mutex, RwLock};
 use url::Url;
 
@@ -39,7 +39,7 @@ impl ResourcePool {
     fn get_or_create_resource(
         &mut self,
         source: &Source,
-        work_dir: &PathBuf,
+        work_dir: &impl CacheLocation,
         platform: &str,
     ) -> Result<Library> {
         if let Some(lib) = self.available.pop() {
@@ -55,8 +55,11 @@ impl ResourcePool {
     }
 
     /// Return a borrowed `Library` to the pool so it can be reused.
+    /// A library whose call has been interrupted is dropped instead.
     fn return_resource(&mut self, lib: Library) {
-        self.available.push(lib);
+        if !lib.is_poisoned() {
+            self.available.push(lib);
+        }
         self.in_use_count -= 1;
     }
 }
@@ -112,7 +115,7 @@ impl Drop for ResourceGuard {
 /// then borrow one `Library` from it (creating a new one if needed).
 fn get_library_resource(
     source: &Source,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     platform: &str,
 ) -> Result<ResourceGuard> {
     // Step 1: look for an existing pool; if not found, create it.
@@ -146,7 +149,7 @@ fn get_library_resource(
 /// to use the same `Source` concurrently, each with its own `Library`.
 pub fn run_multi_cached_with_platform<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     platform: &str,
     run: impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
@@ -165,14 +168,14 @@ pub fn run_multi_cached_with_platform<T>(
 /// Internal fallback logic: tries the current platform, then falls back to "wasm32-wasip1".
 fn run_multi_cached_impl<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     run: &impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
     let this_platform = env!("TARGET_TRIPLE");
     match run_multi_cached_with_platform(url, work_dir, this_platform, run) {
         Ok(v) => Ok(v),
         Err(e) => {
-            if let Some(res_err) = e.downcast_ref::<ResolveError>() {
+            if let Some(res_err @ ResolveError::PlatformNotSupported(_)) = e.downcast_ref() {
                 debug!(
                     "MULTI CACHE: failed with {}, fallback to wasm32-wasip1",
                     res_err
@@ -190,7 +193,7 @@ fn run_multi_cached_impl<T>(
 /// approach under the hood.
 pub fn run_multi_cached<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     run: impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
     run_multi_cached_impl(url, work_dir, &run)
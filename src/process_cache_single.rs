@@ -1,9 +1,9 @@
+use crate::cache::CacheLocation;
 use crate::load::{load_with_platform, Library};
 use crate::resolve::ResolveError;
 use anyhow::Result;
 use log::debug;
 use std::collections::HashMap;
-use std::path::PathBuf;
 use std::sync::{LazyLock, Mutex};
 use url::Url;
 
@@ -31,7 +31,7 @@ static SINGLE_CACHE: LazyLock<Mutex<HashMap<Source, Library>>> =
 /// concurrently (e.g., the library has internal state or side effects that are not thread-safe).
 pub fn run_single_cached_with_platform<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     platform: &str,
     run: impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
@@ -47,7 +47,18 @@ pub fn run_single_cached_with_platform<T>(
     // Check if we already have a Library for this Source
     if let Some(lib) = cache.get_mut(&source) {
         debug!("SINGLE CACHE: found existing library for {}", source.url);
-        return run(lib);
+        let result = run(lib);
+
+        // A library whose call has been interrupted cannot be used anymore.
+        if lib.is_poisoned() {
+            debug!(
+                "SINGLE CACHE: discarding poisoned library for {}",
+                source.url
+            );
+            cache.remove(&source);
+        }
+
+        return result;
     }
 
     // Otherwise, load a new Library and insert it into the cache
@@ -56,7 +67,9 @@ pub fn run_single_cached_with_platform<T>(
     let result = run(&mut lib);
 
     // Insert the library into the cache for future reuse
-    cache.insert(source, lib);
+    if !lib.is_poisoned() {
+        cache.insert(source, lib);
+    }
 
     // Return the result of running the closure
     result
@@ -66,14 +79,14 @@ pub fn run_single_cached_with_platform<T>(
 /// due to a `ResolveError`, falls back to "wasm32-wasip1".
 fn run_single_cached_impl<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     run: &impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
     let this_platform = env!("TARGET_TRIPLE");
     match run_single_cached_with_platform(url, work_dir, this_platform, run) {
         Ok(v) => Ok(v),
         Err(e) => {
-            if let Some(res_err) = e.downcast_ref::<ResolveError>() {
+            if let Some(res_err @ ResolveError::PlatformNotSupported(_)) = e.downcast_ref() {
                 debug!(
                     "SINGLE CACHE: failed to load with {}, fallback to wasm32-wasip1",
                     res_err
@@ -91,7 +104,7 @@ fn run_single_cached_impl<T>(
 /// can't safely run multiple instances concurrently for the same `Source`.
 pub fn run_single_cached<T>(
     url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     run: impl Fn(&mut Library) -> Result<T>,
 ) -> Result<T> {
     run_single_cached_impl(url, work_dir, &run)
@@ -0,0 +1,266 @@
+//! Static registry indexes that map package names and versions to dllpack URLs.
+//!
+//! A registry is served over any URL that dll-pack can fetch (`http`, `https` or `file`),
+//! in one of two forms:
+//!
+//! - A single index file, listing every package:
+//!   ```json
+//!   {
+//!     "registry-version": "1.0.0",
+//!     "packages": {
+//!       "adder": { "versions": { "1.2.0": "adder/1.2.0/adder.dllpack" } }
+//!     }
+//!   }
+//!   ```
+//! - A directory (a URL ending with `/`), with one file per package at `<name>.json`:
+//!   ```json
+//!   { "registry-version": "1.0.0", "versions": { "1.2.0": "adder/1.2.0/adder.dllpack" } }
+//!   ```
+//!
+//! URLs of versions may be relative to the file they are listed in.
+
+use crate::cache::CacheLocation;
+use crate::channel::resolve_channel;
+use crate::download::fetch;
+use crate::fetch_policy::join_reference;
+use crate::load::{load, Library};
+use crate::update::{record_origin, Origin};
+use anyhow::{anyhow, Result};
+use log::debug;
+use semver::{Version, VersionReq};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use url::Url;
+
+/// The versions of a single package, as listed in a registry.
+#[derive(Debug, Clone, Deserialize)]
+struct PackageIndex {
+    #[serde(rename = "registry-version", default)]
+    registry_version: Option<String>,
+    versions: BTreeMap<Version, String>,
+}
+
+/// A registry index file listing every package.
+#[derive(Debug, Clone, Deserialize)]
+struct RegistryIndex {
+    #[serde(rename = "registry-version")]
+    registry_version: String,
+    packages: BTreeMap<String, PackageIndex>,
+}
+
+/// Checks that `name` is a valid package name: ASCII letters, digits, `-`, `_` and `.`,
+/// not starting with `.`. Package names become file names in directory registries,
+/// so they must not be able to point anywhere else.
+fn check_package_name(name: &str) -> Result<()> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if !valid {
+        return Err(anyhow!("Invalid package name: {:?}", name));
+    }
+
+    Ok(())
+}
+
+fn check_registry_version(version: &str) -> Result<()> {
+    if version != "1.0.0" {
+        return Err(anyhow!("Unsupported registry version: {}", version));
+    }
+
+    Ok(())
+}
+
+/// A registry that can be looked up by package name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Registry {
+    pub url: Url,
+}
+
+impl Registry {
+    /// Creates a registry from the URL of an index file,
+    /// or of a directory if the URL ends with `/`.
+    pub fn new(url: Url) -> Self {
+        Self { url }
+    }
+
+    fn is_directory(&self) -> bool {
+        self.url.path().ends_with('/')
+    }
+
+    /// Returns all versions of a package listed in the registry, with their dllpack URLs.
+    /// The registry is fetched on every call, so the result is always up to date.
+    pub fn versions(&self, name: &str) -> Result<BTreeMap<Version, Url>> {
+        check_package_name(name)?;
+
+        let (index_url, package) = if self.is_directory() {
+            let index_url = self.url.join(&format!("{}.json", name))?;
+            debug!("fetching package index: {}", index_url);
+
+            let content = fetch(&index_url)
+                .map_err(|e| anyhow!("Package `{}` not found in {}: {}", name, self.url, e))?;
+            let package: PackageIndex = serde_json::from_slice(&content)?;
+            if let Some(version) = &package.registry_version {
+                check_registry_version(version)?;
+            }
+
+            (index_url, package)
+        } else {
+            debug!("fetching registry index: {}", self.url);
+
+            let content = fetch(&self.url)?;
+            let mut index: RegistryIndex = serde_json::from_slice(&content)?;
+            check_registry_version(&index.registry_version)?;
+
+            let package = index.packages.remove(name).ok_or(anyhow!(
+                "Package `{}` not found in {}",
+                name,
+                self.url
+            ))?;
+
+            (self.url.clone(), package)
+        };
+
+        package
+            .versions
+            .into_iter()
+            .map(|(version, url)| Ok((version, join_reference(&index_url, &url)?)))
+            .collect()
+    }
+
+    /// Returns the newest version of a package that satisfies `requirement`, with its dllpack URL.
+    pub fn find(&self, name: &str, requirement: &VersionReq) -> Result<(Version, Url)> {
+        let versions = self.versions(name)?;
+
+        select_newest(versions, requirement).ok_or(anyhow!(
+            "No version of `{}` in {} satisfies {}",
+            name,
+            self.url,
+            requirement
+        ))
+    }
+}
+
+fn select_newest(
+    versions: BTreeMap<Version, Url>,
+    requirement: &VersionReq,
+) -> Option<(Version, Url)> {
+    versions
+        .into_iter()
+        .rev()
+        .find(|(version, _)| requirement.matches(version))
+}
+
+/// Looks up the newest version of the package `name` that satisfies `requirement`
+/// (a semver requirement such as `"^1.2"`) in `registry`, and loads it like [`load`].
+///
+/// The registry and the requirement are recorded in the cache,
+/// so that [`check_updates`](crate::update::check_updates) can look for newer versions.
+pub fn load_by_name(
+    name: &str,
+    requirement: &str,
+    registry: &Registry,
+    work_dir: &impl CacheLocation,
+) -> Result<Library> {
+    let requirement = VersionReq::parse(requirement)?;
+    let (version, url) = registry.find(name, &requirement)?;
+
+    debug!(
+        "{} {} resolved to {} {}: {}",
+        name, requirement, name, version, url
+    );
+
+    let url = resolve_channel(&url, work_dir)?;
+    record_origin(
+        &url,
+        &Origin::Registry {
+            registry: registry.url.clone(),
+            name: name.to_string(),
+            requirement,
+        },
+        &work_dir.cache_layers().prepare()?,
+    )?;
+
+    load(&url, work_dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::test_dir;
+    use std::fs;
+
+    #[test]
+    fn test_registry_index() {
+        let dir = test_dir("registry");
+        fs::create_dir_all(dir.join("packages")).unwrap();
+
+        fs::write(
+            dir.join("index.json"),
+            r#"{
+                "registry-version": "1.0.0",
+                "packages": {
+                    "adder": {
+                        "versions": {
+                            "1.1.0": "adder/1.1.0/adder.dllpack",
+                            "1.2.3": "adder/1.2.3/adder.dllpack",
+                            "2.0.0": "https://example.com/adder-2.0.0.dllpack"
+                        }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("packages").join("adder.json"),
+            r#"{ "registry-version": "1.0.0", "versions": { "1.2.3": "adder/1.2.3/adder.dllpack" } }"#,
+        )
+        .unwrap();
+
+        // Outside of the directory registry.
+        fs::write(
+            dir.join("outside.json"),
+            r#"{ "versions": { "1.0.0": "https://evil.host/x.dllpack" } }"#,
+        )
+        .unwrap();
+
+        let index = Registry::new(Url::from_file_path(dir.join("index.json")).unwrap());
+        let directory = Registry::new(Url::from_directory_path(dir.join("packages")).unwrap());
+
+        let (version, url) = index
+            .find("adder", &VersionReq::parse("^1.1").unwrap())
+            .unwrap();
+        assert_eq!(version, Version::new(1, 2, 3));
+        assert_eq!(
+            url,
+            Url::from_file_path(dir.join("adder/1.2.3/adder.dllpack")).unwrap()
+        );
+        assert_eq!(index.versions("adder").unwrap().len(), 3);
+        assert!(index
+            .find("adder", &VersionReq::parse("^3").unwrap())
+            .is_err());
+
+        let (version, url) = directory
+            .find("adder", &VersionReq::parse("^1").unwrap())
+            .unwrap();
+        assert_eq!(version, Version::new(1, 2, 3));
+        assert_eq!(
+            url,
+            Url::from_file_path(dir.join("packages/adder/1.2.3/adder.dllpack")).unwrap()
+        );
+
+        for name in [
+            "../outside",
+            "//evil.host/x",
+            "https://evil.host/x",
+            ".hidden",
+            "",
+        ] {
+            assert!(directory.versions(name).is_err(), "{:?}", name);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,16 +1,28 @@
+use crate::advisory::advisory_db;
+use crate::cache;
+use crate::cache::{CacheLayers, CacheLocation};
+use crate::channel::follow_channels;
 use crate::dependency::Dependency;
-use crate::dllpack_file::{DllPackFile, PlatformManifest};
+use crate::dllpack_file::{Metadata, PlatformManifest};
 use crate::download::{cached_download_lib, cached_download_manifest, DllInfo, ManifestInfo};
 use anyhow::{anyhow, Result};
 use log::debug;
+use semver::VersionReq;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt::Display;
-use std::path::PathBuf;
+use std::fs;
+use std::path::{Path, PathBuf};
 use url::Url;
 
 #[derive(Debug)]
 pub enum ResolveError {
     PlatformNotSupported(String),
+    /// The version requirements in the dependency graph cannot be satisfied.
+    /// The string explains the conflict.
+    VersionConflict(String),
+    /// A manifest or library is denied by an [advisory](crate::advisory).
+    /// The string names the advisory and the dependency path.
+    Advisory(String),
 }
 
 impl Display for ResolveError {
@@ -19,23 +31,52 @@ impl Display for ResolveError {
             ResolveError::PlatformNotSupported(platform) => {
                 write!(f, "Platform {} is not supported", platform)
             }
+            ResolveError::VersionConflict(explanation) => {
+                write!(f, "Version conflict: {}", explanation)
+            }
+            ResolveError::Advisory(explanation) => {
+                write!(f, "Denied: {}", explanation)
+            }
         }
     }
 }
 
+impl std::error::Error for ResolveError {}
+
+/// The manifests of a dependency graph for a single platform, collected by `fetch_manifests`.
+#[derive(Debug, Default)]
+pub(crate) struct FetchedManifests {
+    pub(crate) result_map: BTreeMap<ManifestInfo, PlatformManifest>,
+    pub(crate) metadata_map: BTreeMap<ManifestInfo, Metadata>,
+    pub(crate) dependency_map: BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
+    pub(crate) reverse_dependency_map: BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
+    /// Version requirements of dependencies, as (dependent, dependency, requirement).
+    pub(crate) requirements: Vec<(ManifestInfo, ManifestInfo, VersionReq)>,
+    /// The dependency path (URLs from the root) through which each manifest was first reached.
+    pub(crate) paths: BTreeMap<ManifestInfo, Vec<Url>>,
+}
+
 /// Implementation of the DFS process for `fetch_manifests`.
+/// `path` holds the URLs of the manifests from the root down to `base_info`.
 fn fetch_manifests_inner(
     base_info: &ManifestInfo,
-    work_dir: &PathBuf,
+    layers: &CacheLayers,
     platform: &str,
-    result_map: &mut BTreeMap<ManifestInfo, PlatformManifest>,
-    dependency_map: &mut BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
-    reverse_dependency_map: &mut BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
+    fetched: &mut FetchedManifests,
+    path: &mut Vec<Url>,
 ) -> Result<()> {
     cached_download_manifest(&base_info)?;
 
-    let file = DllPackFile::from_file(&base_info.path)?;
+    let file = base_info.read_file()?;
     let manifest = file.manifest;
+    let metadata = file.metadata.unwrap_or_default();
+
+    path.push(base_info.url.clone());
+
+    let db = advisory_db();
+    if let Some(db) = &db {
+        db.check_manifest(&base_info.url, &metadata, path)?;
+    }
 
     let Some(p_manifest) = manifest.platforms.get(platform) else {
         return Err(anyhow!(ResolveError::PlatformNotSupported(
@@ -43,86 +84,207 @@ fn fetch_manifests_inner(
         )));
     };
 
-    result_map.insert(base_info.clone(), p_manifest.clone());
+    if let Some(db) = &db {
+        let main = std::iter::once(p_manifest.lib_spec());
+        let raw_libs = p_manifest
+            .dependencies
+            .iter()
+            .filter_map(Dependency::lib_spec);
+        for spec in main.chain(raw_libs) {
+            db.check_lib(spec.url, spec.sha256, path)?;
+        }
+    }
+
+    fetched.paths.insert(base_info.clone(), path.clone());
+    fetched
+        .result_map
+        .insert(base_info.clone(), p_manifest.clone());
+    fetched.metadata_map.insert(base_info.clone(), metadata);
 
     let mut deps = Vec::new();
 
     for dep in &p_manifest.dependencies {
         match dep {
-            Dependency::DllPack { url } => {
-                let info = ManifestInfo::from_input(url, work_dir)?;
+            Dependency::DllPack { url, version } => {
+                let url = follow_channels(url, layers)?;
+                let info = ManifestInfo::from_layers(&url, layers)?;
                 deps.push(info.clone());
 
-                if !result_map.contains_key(&info) {
-                    fetch_manifests_inner(
-                        &info,
-                        work_dir,
-                        platform,
-                        result_map,
-                        dependency_map,
-                        reverse_dependency_map,
-                    )?;
+                if !fetched.result_map.contains_key(&info) {
+                    fetch_manifests_inner(&info, layers, platform, fetched, path)?;
                 }
 
-                reverse_dependency_map
+                fetched
+                    .reverse_dependency_map
                     .entry(info.clone())
                     .or_insert_with(Vec::new)
                     .push(base_info.clone());
+
+                if let Some(version) = version {
+                    fetched
+                        .requirements
+                        .push((base_info.clone(), info, version.clone()));
+                }
             }
             _ => {}
         }
     }
 
-    dependency_map.insert(base_info.clone(), deps);
+    fetched.dependency_map.insert(base_info.clone(), deps);
+
+    path.pop();
 
     Ok(())
 }
 
 /// Recursively downloads and processes manifests using DFS, building a map of dependencies
 /// and reverse dependencies.
-fn fetch_manifests(
+/// Channels are followed, so the returned manifests have concrete URLs.
+pub(crate) fn fetch_manifests(
     base_url: &Url,
-    work_dir: &PathBuf,
+    layers: &CacheLayers,
     platform: &str,
-) -> Result<(
-    ManifestInfo,
-    BTreeMap<ManifestInfo, PlatformManifest>,
-    BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
-    BTreeMap<ManifestInfo, Vec<ManifestInfo>>,
-)> {
-    let mut result_map = BTreeMap::new();
-    let mut dependency_map = BTreeMap::new();
-    let mut reverse_dependency_map = BTreeMap::new();
-
-    let base_info = ManifestInfo::from_input(base_url, work_dir)?;
-
-    fetch_manifests_inner(
-        &base_info,
-        work_dir,
-        platform,
-        &mut result_map,
-        &mut dependency_map,
-        &mut reverse_dependency_map,
-    )?;
-
-    Ok((
-        base_info,
-        result_map,
-        dependency_map,
-        reverse_dependency_map,
-    ))
+) -> Result<(ManifestInfo, FetchedManifests)> {
+    fetch_concrete_manifests(&follow_channels(base_url, layers)?, layers, platform)
+}
+
+/// Like [`fetch_manifests`], for a `base_url` whose channels have already been followed.
+fn fetch_concrete_manifests(
+    base_url: &Url,
+    layers: &CacheLayers,
+    platform: &str,
+) -> Result<(ManifestInfo, FetchedManifests)> {
+    let mut fetched = FetchedManifests::default();
+    let base_info = ManifestInfo::from_layers(base_url, layers)?;
+
+    fetch_manifests_inner(&base_info, layers, platform, &mut fetched, &mut Vec::new())?;
+
+    Ok((base_info, fetched))
+}
+
+/// Describes who depends on a manifest, for error messages.
+fn describe_dependents(fetched: &FetchedManifests, info: &ManifestInfo) -> String {
+    match fetched.reverse_dependency_map.get(info) {
+        Some(dependents) => {
+            let urls: Vec<_> = dependents.iter().map(|d| d.url.as_str()).collect();
+            format!("required by {}", urls.join(", "))
+        }
+        None => "the root".to_string(),
+    }
+}
+
+/// Checks that every package name appears only once in the graph,
+/// and that every version requirement is satisfied.
+pub(crate) fn check_versions(fetched: &FetchedManifests) -> Result<()> {
+    let mut packages: BTreeMap<&str, Vec<&ManifestInfo>> = BTreeMap::new();
+    for (info, metadata) in &fetched.metadata_map {
+        if let Some(name) = &metadata.name {
+            packages.entry(name.as_str()).or_default().push(info);
+        }
+    }
+
+    for (name, infos) in packages {
+        if infos.len() < 2 {
+            continue;
+        }
+
+        let lines: Vec<_> = infos
+            .iter()
+            .map(|info| {
+                let version = match &fetched.metadata_map[*info].version {
+                    Some(v) => v.to_string(),
+                    None => "(no version)".to_string(),
+                };
+                format!(
+                    "  - {} from {} ({})",
+                    version,
+                    info.url,
+                    describe_dependents(fetched, info)
+                )
+            })
+            .collect();
+
+        return Err(anyhow!(ResolveError::VersionConflict(format!(
+            "package `{}` would be loaded more than once:\n{}",
+            name,
+            lines.join("\n")
+        ))));
+    }
+
+    for (dependent, dependency, requirement) in &fetched.requirements {
+        let metadata = &fetched.metadata_map[dependency];
+        let name = metadata.name.as_deref().unwrap_or("(unnamed)");
+
+        match &metadata.version {
+            Some(version) if requirement.matches(version) => {}
+            Some(version) => {
+                return Err(anyhow!(ResolveError::VersionConflict(format!(
+                    "{} requires `{}` {}, but {} provides version {}",
+                    dependent.url, name, requirement, dependency.url, version
+                ))));
+            }
+            None => {
+                return Err(anyhow!(ResolveError::VersionConflict(format!(
+                    "{} requires `{}` {}, but {} declares no version",
+                    dependent.url, name, requirement, dependency.url
+                ))));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks a downloaded library against the advisories that match by content hash.
+/// Libraries with a declared hash have already been checked by `fetch_manifests`.
+fn check_lib_content(dll_info: &DllInfo, path: &[Url]) -> Result<()> {
+    let Some(db) = advisory_db() else {
+        return Ok(());
+    };
+    if dll_info.sha256.is_some() || !db.has_hash_advisories() {
+        return Ok(());
+    }
+
+    let sha256 = cache::sha256_hex(&fs::read(&dll_info.path)?);
+    db.check_lib(&dll_info.url, Some(&sha256), path)
 }
 
 /// Resolves dependencies, ensuring all necessary libraries are downloaded
 /// and available in the correct order.
 /// Return value is a tuple of the main library and a vector of dependencies.
+///
+/// If `base_url` or a dependency is a [channel](crate::channel), it is followed to the concrete dllpack.
+///
+/// Manifests and libraries are checked against the [advisory database](crate::advisory),
+/// and denied ones fail with [`ResolveError::Advisory`].
+///
+/// `work_dir` is either a single cache directory or a [`CacheLayers`] stack.
 pub fn resolve(
     base_url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &impl CacheLocation,
     platform: &str,
 ) -> Result<(DllInfo, Vec<DllInfo>)> {
-    let (base_info, result_map, dependency_map, reverse_dependency_map) =
-        fetch_manifests(base_url, work_dir, platform)?;
+    let layers = work_dir.cache_layers().prepare()?;
+
+    resolve_concrete(&follow_channels(base_url, &layers)?, &layers, platform)
+}
+
+/// Like [`resolve`], for a `base_url` whose channels have already been followed.
+pub(crate) fn resolve_concrete(
+    base_url: &Url,
+    layers: &CacheLayers,
+    platform: &str,
+) -> Result<(DllInfo, Vec<DllInfo>)> {
+    let (base_info, fetched) = fetch_concrete_manifests(base_url, layers, platform)?;
+    check_versions(&fetched)?;
+
+    let FetchedManifests {
+        result_map,
+        dependency_map,
+        reverse_dependency_map,
+        paths,
+        ..
+    } = fetched;
 
     let mut available = Vec::new();
     let mut remain_deps_counts =
@@ -134,7 +296,7 @@ pub fn resolve(
         if count == &0 {
             available.push(m_info.clone());
             unresolved_count -= 1;
-            if &m_info.url != base_url {
+            if m_info != &base_info {
                 dependency_load_order.push(m_info.clone());
             }
         }
@@ -151,7 +313,7 @@ pub fn resolve(
                 available.push(dep.clone());
                 unresolved_count -= 1;
 
-                if &dep.url != base_url {
+                if dep != &base_info {
                     dependency_load_order.push(dep.clone());
                 }
             }
@@ -170,22 +332,16 @@ pub fn resolve(
     for m_info in dependency_load_order.iter() {
         let manifest = result_map.get(m_info).unwrap();
 
-        let dll_info = DllInfo::from_input(
-            &manifest.url,
-            &manifest.name.as_ref().map(String::as_str),
-            work_dir,
-        )?;
+        let dll_info = DllInfo::from_layers(&manifest.lib_spec(), layers)?;
         cached_download_lib(&dll_info)?;
+        check_lib_content(&dll_info, &paths[m_info])?;
         dependency_load_order_paths.push(dll_info);
     }
 
     let manifest = result_map.get(&base_info).unwrap();
-    let dll_info = DllInfo::from_input(
-        &manifest.url,
-        &manifest.name.as_ref().map(String::as_str),
-        work_dir,
-    )?;
+    let dll_info = DllInfo::from_layers(&manifest.lib_spec(), layers)?;
     cached_download_lib(&dll_info)?;
+    check_lib_content(&dll_info, &paths[&base_info])?;
 
     Ok((dll_info, dependency_load_order_paths))
 }
@@ -201,8 +357,11 @@ pub fn resolve(
 /// * `Err(...)` If some I/O or parsing error occurs.
 pub fn get_all_cached_dependencies(
     dllpack_url: &Url,
-    work_dir: &PathBuf,
+    work_dir: &Path,
 ) -> Result<Option<Vec<(String, PathBuf)>>> {
+    cache::prepare(work_dir)?;
+    let work_dir = &cache::current_generation(work_dir)?;
+
     // Build a ManifestInfo for the top-level URL
     let base_info = ManifestInfo::from_input(dllpack_url, work_dir)?;
 
@@ -212,12 +371,18 @@ pub fn get_all_cached_dependencies(
     }
 
     // Parse the top-level dllpack file
-    let base_file = DllPackFile::from_file(&base_info.path)
+    let (base_file, bundle_dir) = base_info
+        .read_file_with_bundle_dir()
         .map_err(|e| anyhow!("Failed to parse the main dllpack file: {}", e))?;
 
     // Prepare a result structure
     let mut result = vec![(base_info.url.to_string(), base_info.path.clone())];
 
+    // If the dllpack is a bundle, its unpacked contents are cached as well
+    if let Some(dir) = bundle_dir {
+        result.push((base_info.url.to_string(), dir));
+    }
+
     debug!("aa {:?}", base_file);
 
     // We'll do a BFS (or DFS) to traverse all dependent dllpacks across all platforms,
@@ -232,8 +397,7 @@ pub fn get_all_cached_dependencies(
     while let Some(current_file) = queue.pop_front() {
         // For each platform in the current dllpack, gather dependencies
         for (_platform_name, p_manifest) in &current_file.manifest.platforms {
-            let dll_info =
-                DllInfo::from_input(&p_manifest.url, &p_manifest.name.as_deref(), work_dir)?;
+            let dll_info = DllInfo::from_spec(&p_manifest.lib_spec(), work_dir)?;
             if let Some(p) = dll_info.exist_cache_dir() {
                 result.push((dll_info.url.to_string(), p));
             }
@@ -241,25 +405,29 @@ pub fn get_all_cached_dependencies(
             for dep in &p_manifest.dependencies {
                 match dep {
                     // If the dependency is another dllpack, check if it's cached
-                    Dependency::DllPack { url } => {
+                    Dependency::DllPack { url, .. } => {
                         let sub_info = ManifestInfo::from_input(url, work_dir)?;
 
                         // If we haven't visited this sub-manifest yet and it's cached locally
                         if !visited_manifests.contains(&sub_info) && sub_info.path.exists() {
                             // Parse it
-                            let sub_file = DllPackFile::from_file(&sub_info.path).map_err(|e| {
-                                anyhow!("Failed to parse a dependent dllpack file: {}", e)
-                            })?;
+                            let (sub_file, bundle_dir) =
+                                sub_info.read_file_with_bundle_dir().map_err(|e| {
+                                    anyhow!("Failed to parse a dependent dllpack file: {}", e)
+                                })?;
                             // Record it in the dependency list
                             result.push((url.to_string(), sub_info.path.clone()));
+                            if let Some(dir) = bundle_dir {
+                                result.push((url.to_string(), dir));
+                            }
                             // Mark as visited and enqueue
                             visited_manifests.insert(sub_info);
                             queue.push_back(sub_file);
                         }
                     }
                     // If the dependency is a direct Dll
-                    Dependency::RawLib { url, name } => {
-                        let dll_info = DllInfo::from_input(url, &name.as_deref(), work_dir)?;
+                    Dependency::RawLib { .. } => {
+                        let dll_info = DllInfo::from_spec(&dep.lib_spec().unwrap(), work_dir)?;
                         // If it's actually present, record it
                         if let Some(p) = dll_info.exist_cache_dir() {
                             result.push((dll_info.url.to_string(), p));
@@ -289,4 +457,65 @@ mod tests {
 
         println!("{:?}", result);
     }
+
+    fn manifest_info(url: &str) -> ManifestInfo {
+        let work_dir = PathBuf::from_str("/nonexistent").unwrap();
+        ManifestInfo::from_input(&Url::from_str(url).unwrap(), &work_dir).unwrap()
+    }
+
+    fn metadata(name: &str, version: &str) -> Metadata {
+        Metadata {
+            name: Some(name.to_string()),
+            version: Some(semver::Version::parse(version).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check_versions() {
+        let root = manifest_info("https://example.com/root.dllpack");
+        let adder = manifest_info("https://example.com/adder-1.2.0.dllpack");
+
+        let mut fetched = FetchedManifests::default();
+        fetched
+            .metadata_map
+            .insert(root.clone(), metadata("root", "0.1.0"));
+        fetched
+            .metadata_map
+            .insert(adder.clone(), metadata("adder", "1.2.0"));
+        fetched
+            .reverse_dependency_map
+            .insert(adder.clone(), vec![root.clone()]);
+
+        fetched.requirements = vec![(
+            root.clone(),
+            adder.clone(),
+            VersionReq::parse("^1.1").unwrap(),
+        )];
+        assert!(check_versions(&fetched).is_ok());
+
+        fetched.requirements = vec![(root, adder, VersionReq::parse("^2").unwrap())];
+        let err = check_versions(&fetched).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref(),
+            Some(ResolveError::VersionConflict(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_versions_duplicate_package() {
+        let mut fetched = FetchedManifests::default();
+        fetched.metadata_map.insert(
+            manifest_info("https://example.com/adder-1.2.0.dllpack"),
+            metadata("adder", "1.2.0"),
+        );
+        fetched.metadata_map.insert(
+            manifest_info("https://example.com/adder-2.0.0.dllpack"),
+            metadata("adder", "2.0.0"),
+        );
+
+        let err = check_versions(&fetched).unwrap_err().to_string();
+        assert!(err.contains("package `adder` would be loaded more than once"));
+        assert!(err.contains("adder-2.0.0.dllpack"));
+    }
 }
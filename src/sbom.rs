@@ -0,0 +1,278 @@
+//! Software bills of materials (SBOMs) of resolved dllpack graphs, in CycloneDX JSON format.
+
+use crate::cache::{self, CacheLocation};
+use crate::dependency::Dependency;
+use crate::dllpack_file::{LibSpec, Metadata};
+use crate::download::{cached_download_lib, DllInfo};
+use crate::resolve::{check_versions, fetch_manifests};
+use anyhow::Result;
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+/// The version of the CycloneDX specification that generated documents follow.
+pub const CYCLONEDX_SPEC_VERSION: &str = "1.5";
+
+/// The last path segment of a URL, used as a name when nothing better is available.
+fn file_name_of(url: &Url) -> String {
+    url.path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or(url.as_str())
+        .to_string()
+}
+
+fn sha256_of_file(path: &Path) -> Result<String> {
+    Ok(cache::sha256_hex(&fs::read(path)?))
+}
+
+fn hashes(sha256: &str) -> Value {
+    json!([{ "alg": "SHA-256", "content": sha256 }])
+}
+
+/// The component of a dllpack manifest.
+fn manifest_component(url: &Url, manifest_path: &Path, metadata: &Metadata) -> Result<Value> {
+    let mut component = Map::new();
+    component.insert("type".into(), json!("library"));
+    component.insert("bom-ref".into(), json!(url.as_str()));
+
+    let name = match &metadata.name {
+        Some(name) => name.clone(),
+        None => {
+            let file_name = file_name_of(url);
+            file_name
+                .strip_suffix(".dllpack")
+                .unwrap_or(&file_name)
+                .to_string()
+        }
+    };
+    component.insert("name".into(), json!(name));
+
+    if let Some(version) = &metadata.version {
+        component.insert("version".into(), json!(version.to_string()));
+    }
+    if let Some(description) = &metadata.description {
+        component.insert("description".into(), json!(description));
+    }
+    if !metadata.authors.is_empty() {
+        component.insert("author".into(), json!(metadata.authors.join(", ")));
+    }
+    if let Some(license) = &metadata.license {
+        component.insert("licenses".into(), json!([{ "expression": license }]));
+    }
+    component.insert("hashes".into(), hashes(&sha256_of_file(manifest_path)?));
+
+    let mut references = vec![json!({ "type": "distribution", "url": url.as_str() })];
+    if let Some(repository) = &metadata.repository {
+        references.push(json!({ "type": "vcs", "url": repository.as_str() }));
+    }
+    if let Some(homepage) = &metadata.homepage {
+        references.push(json!({ "type": "website", "url": homepage.as_str() }));
+    }
+    component.insert("externalReferences".into(), Value::Array(references));
+
+    Ok(Value::Object(component))
+}
+
+/// The component of a library file.
+/// The hash declared by the manifest is used, or otherwise computed from the cached file.
+fn artifact_component(dll_info: &DllInfo) -> Result<Value> {
+    let sha256 = match &dll_info.sha256 {
+        Some(sha256) => sha256.clone(),
+        None => sha256_of_file(&dll_info.path)?,
+    };
+
+    Ok(json!({
+        "type": "file",
+        "bom-ref": dll_info.url.as_str(),
+        "name": dll_info.name,
+        "hashes": hashes(&sha256),
+        "externalReferences": [{ "type": "distribution", "url": dll_info.url.as_str() }],
+    }))
+}
+
+/// Resolves the dllpack at `url` for `platform`, like [`resolve`](crate::resolve::resolve),
+/// and describes every manifest and library of the graph as a CycloneDX SBOM.
+///
+/// Manifests are `library` components and libraries are `file` components,
+/// both identified by their URLs. The `dependencies` section records which libraries
+/// each manifest provides and which dllpacks it depends on.
+///
+/// All libraries of the graph, including `rawlib` dependencies, are downloaded to compute their hashes.
+pub fn cyclonedx(url: &Url, work_dir: &impl CacheLocation, platform: &str) -> Result<Value> {
+    let layers = work_dir.cache_layers().prepare()?;
+
+    let (base_info, fetched) = fetch_manifests(url, &layers, platform)?;
+    check_versions(&fetched)?;
+
+    let mut components = BTreeMap::new();
+    let mut dependencies = Vec::new();
+    let mut root_component = Value::Null;
+
+    for (m_info, p_manifest) in &fetched.result_map {
+        let metadata = fetched
+            .metadata_map
+            .get(m_info)
+            .cloned()
+            .unwrap_or_default();
+        let component = manifest_component(&m_info.url, &m_info.path, &metadata)?;
+
+        if m_info == &base_info {
+            root_component = component;
+        } else {
+            components.insert(m_info.url.to_string(), component);
+        }
+
+        let mut specs: Vec<LibSpec> = vec![p_manifest.lib_spec()];
+        specs.extend(
+            p_manifest
+                .dependencies
+                .iter()
+                .filter_map(Dependency::lib_spec),
+        );
+
+        let mut depends_on = Vec::new();
+
+        for spec in specs {
+            let dll_info = DllInfo::from_layers(&spec, &layers)?;
+            cached_download_lib(&dll_info)?;
+
+            depends_on.push(dll_info.url.to_string());
+            if !components.contains_key(dll_info.url.as_str()) {
+                components.insert(dll_info.url.to_string(), artifact_component(&dll_info)?);
+                dependencies.push(json!({ "ref": dll_info.url.as_str(), "dependsOn": [] }));
+            }
+        }
+
+        for dep in fetched.dependency_map.get(m_info).into_iter().flatten() {
+            depends_on.push(dep.url.to_string());
+        }
+
+        dependencies.push(json!({ "ref": m_info.url.as_str(), "dependsOn": depends_on }));
+    }
+
+    Ok(json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": CYCLONEDX_SPEC_VERSION,
+        "version": 1,
+        "metadata": {
+            "tools": {
+                "components": [{
+                    "type": "application",
+                    "name": env!("CARGO_PKG_NAME"),
+                    "version": env!("CARGO_PKG_VERSION"),
+                }],
+            },
+            "component": root_component,
+            "properties": [{ "name": "dll-pack:platform", "value": platform }],
+        },
+        "components": components.into_values().collect::<Vec<_>>(),
+        "dependencies": dependencies,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{test_dir, write_dep, write_dllpack_with_metadata, PLATFORM};
+
+    #[test]
+    fn test_cyclonedx() {
+        let dir = test_dir("sbom-test");
+        let src_dir = dir.join("src");
+        fs::create_dir_all(&src_dir).unwrap();
+
+        let app_sha256 = cache::sha256_hex(b"app");
+        write_dllpack_with_metadata(
+            &src_dir,
+            "app.dllpack",
+            json!({ "name": "app", "version": "1.2.0", "license": "MIT" }),
+            json!({
+                "url": "app.wasm",
+                "sha256": app_sha256,
+                "dependencies": [
+                    { "type": "dllpack", "url": "dep.dllpack" },
+                    { "type": "rawlib", "url": "raw.wasm" }
+                ]
+            }),
+        );
+        write_dep(&src_dir);
+        fs::write(src_dir.join("app.wasm"), "app").unwrap();
+        fs::write(src_dir.join("raw.wasm"), "raw").unwrap();
+
+        let url_of = |name: &str| Url::from_file_path(src_dir.join(name)).unwrap().to_string();
+        let app_url = Url::from_file_path(src_dir.join("app.dllpack")).unwrap();
+        let sbom = cyclonedx(&app_url, &dir.join("work"), PLATFORM).unwrap();
+
+        assert_eq!(sbom["bomFormat"], "CycloneDX");
+        let root = &sbom["metadata"]["component"];
+        assert_eq!(root["bom-ref"], url_of("app.dllpack"));
+        assert_eq!(root["name"], "app");
+        assert_eq!(root["version"], "1.2.0");
+        assert_eq!(root["licenses"][0]["expression"], "MIT");
+        assert_eq!(
+            root["hashes"][0]["content"],
+            sha256_of_file(&src_dir.join("app.dllpack")).unwrap()
+        );
+
+        // Every library and dependency pack is a component; the root is not.
+        let components: BTreeMap<String, &Value> = sbom["components"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|c| (c["bom-ref"].as_str().unwrap().to_string(), c))
+            .collect();
+        assert_eq!(
+            components.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                url_of("app.wasm"),
+                url_of("dep.dllpack"),
+                url_of("dep.wasm"),
+                url_of("raw.wasm"),
+            ]
+        );
+        assert_eq!(components[&url_of("dep.dllpack")]["type"], "library");
+        assert_eq!(components[&url_of("dep.dllpack")]["name"], "dep");
+        assert_eq!(components[&url_of("app.wasm")]["type"], "file");
+        assert_eq!(
+            components[&url_of("app.wasm")]["hashes"][0]["content"],
+            app_sha256
+        );
+        assert_eq!(
+            components[&url_of("raw.wasm")]["hashes"][0]["content"],
+            cache::sha256_hex(b"raw")
+        );
+
+        let dependencies: BTreeMap<String, Vec<String>> = sbom["dependencies"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|d| {
+                let depends_on = d["dependsOn"].as_array().unwrap().iter();
+                (
+                    d["ref"].as_str().unwrap().to_string(),
+                    depends_on
+                        .map(|r| r.as_str().unwrap().to_string())
+                        .collect(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            dependencies[&url_of("app.dllpack")],
+            vec![
+                url_of("app.wasm"),
+                url_of("raw.wasm"),
+                url_of("dep.dllpack")
+            ]
+        );
+        assert_eq!(
+            dependencies[&url_of("dep.dllpack")],
+            vec![url_of("dep.wasm")]
+        );
+        assert!(dependencies[&url_of("raw.wasm")].is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
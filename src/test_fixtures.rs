@@ -0,0 +1,63 @@
+//! Dllpacks on disk shared by the tests of several modules.
+
+use crate::cache;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// The platform that the fixture dllpacks are written for.
+pub(crate) const PLATFORM: &str = "wasm32-wasip1";
+
+/// An empty temporary directory for the test `what`, unique to this process.
+pub(crate) fn test_dir(what: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dll-pack-{}-{}", what, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes the dllpack `name` into `dir`, with `platform` as its manifest for [`PLATFORM`].
+pub(crate) fn write_dllpack(dir: &Path, name: &str, platform: Value) {
+    write_dllpack_with_metadata(dir, name, Value::Null, platform);
+}
+
+/// Like [`write_dllpack`], with the package `metadata`.
+pub(crate) fn write_dllpack_with_metadata(
+    dir: &Path,
+    name: &str,
+    metadata: Value,
+    platform: Value,
+) {
+    let pack = json!({
+        "spec-version": "1.0.0",
+        "metadata": metadata,
+        "manifest": { "platforms": { PLATFORM: platform } }
+    });
+    fs::write(dir.join(name), pack.to_string()).unwrap();
+}
+
+/// Writes `dep.dllpack` into `dir`, with the library `dep.wasm` that contains `dep`.
+pub(crate) fn write_dep(dir: &Path) {
+    write_dllpack(dir, "dep.dllpack", json!({ "url": "dep.wasm" }));
+    fs::write(dir.join("dep.wasm"), "dep").unwrap();
+}
+
+/// Writes `app.dllpack` into `dir`, with the library `app.wasm` that contains `app`
+/// and declares its hash, and a dependency on [`write_dep`]'s `dep.dllpack`.
+/// Returns the URL of `app.dllpack`.
+pub(crate) fn write_app_and_dep(dir: &Path, app: &str) -> Url {
+    write_dllpack(
+        dir,
+        "app.dllpack",
+        json!({
+            "url": "app.wasm",
+            "sha256": cache::sha256_hex(app.as_bytes()),
+            "dependencies": [{ "type": "dllpack", "url": "dep.dllpack" }]
+        }),
+    );
+    fs::write(dir.join("app.wasm"), app).unwrap();
+    write_dep(dir);
+
+    Url::from_file_path(dir.join("app.dllpack")).unwrap()
+}
@@ -0,0 +1,534 @@
+//! Detection and application of updates of cached dllpacks.
+//!
+//! A root dllpack is compared with its upstream version, which is looked up through
+//! the channel or registry it was loaded from (its [`Origin`]), or at its own URL otherwise.
+
+use crate::bundle;
+use crate::cache::{self, CacheLayers, CacheLocation};
+use crate::channel::fetch_following_channels;
+use crate::dependency::Dependency;
+use crate::dllpack_file::DllPackFile;
+use crate::download::{
+    cached_download_lib, cached_download_manifest, module_cache_file_name, DllInfo, ManifestInfo,
+};
+use crate::inspect::list_cached_packs;
+use crate::registry::Registry;
+use crate::resolve::{fetch_manifests, resolve, ResolveError};
+use anyhow::Result;
+use log::{debug, warn};
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+/// Where a dllpack was loaded from, when it was not loaded by its own URL.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Origin {
+    /// Loaded through a [channel](crate::channel).
+    Channel {
+        #[serde(with = "url_serde")]
+        url: Url,
+    },
+    /// Loaded by name from a [registry](crate::registry).
+    Registry {
+        #[serde(with = "url_serde")]
+        registry: Url,
+        name: String,
+        requirement: VersionReq,
+    },
+}
+
+fn origin_path(work_dir: &Path, url: &Url) -> PathBuf {
+    work_dir.join(cache::ORIGINS_DIR).join(cache::url_key(url))
+}
+
+/// Records that the dllpack at `url` was loaded from `origin`.
+pub(crate) fn record_origin(url: &Url, origin: &Origin, layers: &CacheLayers) -> Result<()> {
+    let Some(dir) = layers.writable_dir() else {
+        return Ok(());
+    };
+
+    let path = origin_path(dir, url);
+    let content = serde_json::to_vec(origin)?;
+    if fs::read(&path).ok().as_deref() == Some(content.as_slice()) {
+        return Ok(());
+    }
+
+    cache::write_atomic(&path, &content)
+}
+
+/// Returns where the cached dllpack at `url` was loaded from,
+/// or `None` if it was loaded by its own URL.
+pub fn origin_of(url: &Url, work_dir: &Path) -> Result<Option<Origin>> {
+    let path = origin_path(&cache::current_generation(work_dir)?, url);
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(serde_json::from_slice(&fs::read(path)?)?))
+}
+
+/// A library of a platform that differs between the cached and the upstream dllpack.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtifactChange {
+    pub platform: String,
+    /// The file name of the library.
+    pub name: String,
+    /// The URL of the cached library, or `None` if it was added upstream.
+    pub old_url: Option<Url>,
+    /// The URL of the upstream library, or `None` if it was removed upstream.
+    pub new_url: Option<Url>,
+}
+
+/// An available update of a cached root dllpack.
+#[derive(Debug, Clone)]
+pub struct Update {
+    /// The URL of the cached dllpack.
+    pub url: Url,
+    pub origin: Option<Origin>,
+    /// The concrete URL of the upstream dllpack.
+    /// It is the same as `url` if the dllpack was changed in place.
+    pub new_url: Url,
+    pub current_version: Option<Version>,
+    pub new_version: Option<Version>,
+    /// The libraries of the dllpack itself (not of its dependency packs) that differ.
+    pub artifacts: Vec<ArtifactChange>,
+}
+
+/// The libraries of every platform of a dllpack, as (url, sha256) by (platform, file name).
+type Artifacts = BTreeMap<(String, String), (Url, Option<String>)>;
+
+fn artifacts_of(file: &DllPackFile, work_dir: &PathBuf) -> Result<Artifacts> {
+    let mut result = BTreeMap::new();
+
+    for (platform, p_manifest) in &file.manifest.platforms {
+        let mut specs = vec![p_manifest.lib_spec()];
+        specs.extend(
+            p_manifest
+                .dependencies
+                .iter()
+                .filter_map(Dependency::lib_spec),
+        );
+
+        for spec in specs {
+            let info = DllInfo::from_spec(&spec, work_dir)?;
+            result.insert((platform.clone(), info.name), (info.url, info.sha256));
+        }
+    }
+
+    Ok(result)
+}
+
+fn diff_artifacts(
+    current: &DllPackFile,
+    new: &DllPackFile,
+    work_dir: &PathBuf,
+) -> Result<Vec<ArtifactChange>> {
+    let old = artifacts_of(current, work_dir)?;
+    let new = artifacts_of(new, work_dir)?;
+
+    let keys: BTreeSet<_> = old.keys().chain(new.keys()).collect();
+
+    Ok(keys
+        .into_iter()
+        .filter(|key| old.get(*key) != new.get(*key))
+        .map(|(platform, name)| ArtifactChange {
+            platform: platform.clone(),
+            name: name.clone(),
+            old_url: old
+                .get(&(platform.clone(), name.clone()))
+                .map(|a| a.0.clone()),
+            new_url: new
+                .get(&(platform.clone(), name.clone()))
+                .map(|a| a.0.clone()),
+        })
+        .collect())
+}
+
+/// Checks a single cached dllpack for an update.
+fn check_update(url: &Url, work_dir: &PathBuf) -> Result<Option<Update>> {
+    let origin = origin_of(url, work_dir)?;
+    let current = ManifestInfo::from_input(url, work_dir)?.read_file()?;
+
+    let upstream_url = match &origin {
+        None => url.clone(),
+        Some(Origin::Channel { url }) => url.clone(),
+        Some(Origin::Registry {
+            registry,
+            name,
+            requirement,
+        }) => Registry::new(registry.clone()).find(name, requirement)?.1,
+    };
+
+    debug!("checking for updates: {} (upstream: {})", url, upstream_url);
+
+    let (new_url, content) = fetch_following_channels(&upstream_url)?;
+    // The bundle is only unpacked into the cache when the update is applied.
+    let new = if bundle::is_bundle(&content) {
+        bundle::read_bundle_manifest(&content, work_dir)?
+    } else {
+        DllPackFile::from_str_with_base(std::str::from_utf8(&content)?, &new_url)?
+    };
+
+    if &new_url == url && serde_json::to_value(&new)? == serde_json::to_value(&current)? {
+        return Ok(None);
+    }
+
+    Ok(Some(Update {
+        url: url.clone(),
+        origin,
+        artifacts: diff_artifacts(&current, &new, work_dir)?,
+        current_version: current.metadata.and_then(|m| m.version),
+        new_version: new.metadata.and_then(|m| m.version),
+        new_url,
+    }))
+}
+
+/// Compares every cached root dllpack in `work_dir` with its upstream version,
+/// and returns the ones that have changed.
+///
+/// Dllpacks that cannot be checked (for example, because the server is unreachable)
+/// are skipped with a warning.
+pub fn check_updates(work_dir: &Path) -> Result<Vec<Update>> {
+    cache::prepare(work_dir)?;
+    let work_dir = &cache::current_generation(work_dir)?;
+    let mut updates = Vec::new();
+
+    for pack in list_cached_packs(work_dir)? {
+        if !pack.is_root {
+            continue;
+        }
+
+        match check_update(&pack.url, work_dir) {
+            Ok(Some(update)) => updates.push(update),
+            Ok(None) => {}
+            Err(e) => warn!("could not check {} for updates: {}", pack.url, e),
+        }
+    }
+
+    Ok(updates)
+}
+
+/// Moves the entries of `from` into `to`, keeping entries that already exist in `to`.
+fn move_new_entries(from: &Path, to: &Path) -> Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let target = to.join(entry.file_name());
+        if !target.exists() {
+            fs::rename(entry.path(), target)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the entries of `from` into `to`, atomically replacing entries that already exist.
+fn replace_entries(from: &Path, to: &Path, last: Option<&str>) -> Result<()> {
+    if !from.exists() {
+        return Ok(());
+    }
+    fs::create_dir_all(to)?;
+
+    let mut entries: Vec<_> = fs::read_dir(from)?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<Result<_, _>>()?;
+    entries.sort_by_key(|name| Some(name.to_string_lossy().as_ref()) == last);
+
+    for name in entries {
+        fs::rename(from.join(&name), to.join(&name))?;
+    }
+
+    Ok(())
+}
+
+/// Moves everything downloaded into `staging` over to `generation`,
+/// a snapshot of the current generation that is not published yet.
+///
+/// Files are replaced with `rename`, so the hard links of the current generation,
+/// and processes that have the old files loaded, keep the old content.
+fn switch_over(staging: &Path, generation: &Path, root_url: &Url) -> Result<()> {
+    // Content addressed entries never change, so existing ones are kept.
+    move_new_entries(&cache::blobs_dir(staging), &cache::blobs_dir(generation))?;
+    move_new_entries(
+        &staging.join(cache::BUNDLES_DIR),
+        &generation.join(cache::BUNDLES_DIR),
+    )?;
+    move_new_entries(
+        &staging.join(cache::INDEX_DIR),
+        &generation.join(cache::INDEX_DIR),
+    )?;
+
+    for entry in fs::read_dir(staging)? {
+        let entry = entry?;
+        let key = entry.file_name();
+        if !entry.file_type()?.is_dir() || key.to_string_lossy().starts_with('_') {
+            continue;
+        }
+
+        for lib in fs::read_dir(entry.path())? {
+            let lib = lib?;
+            let lib_dir = generation.join(&key);
+            cache::link_or_copy(&lib.path(), &lib_dir.join(lib.file_name()))?;
+
+            // Modules compiled from the replaced library are stale.
+            let name = lib.file_name().to_string_lossy().to_string();
+            for consume_fuel in [false, true] {
+                let module_cache = lib_dir.join(module_cache_file_name(&name, consume_fuel));
+                if module_cache.exists() {
+                    fs::remove_file(module_cache)?;
+                }
+            }
+        }
+    }
+
+    replace_entries(
+        &staging.join(cache::CHANNELS_DIR),
+        &generation.join(cache::CHANNELS_DIR),
+        None,
+    )?;
+    replace_entries(
+        &staging.join(cache::MANIFESTS_DIR),
+        &generation.join(cache::MANIFESTS_DIR),
+        Some(&cache::url_key(root_url)),
+    )?;
+
+    Ok(())
+}
+
+/// Downloads the manifests of the new graph for `platform` into `staging`,
+/// together with the libraries that changed in place.
+/// Everything else is resolved from the current generation `current`
+/// and only downloaded if it is not cached yet.
+fn stage_platform(url: &Url, current: &Path, staging: &PathBuf, platform: &str) -> Result<()> {
+    // Manifests are always downloaded again, as they may have changed in place.
+    let (_, fetched) = fetch_manifests(url, &staging.cache_layers(), platform)?;
+
+    for p_manifest in fetched.result_map.values() {
+        let spec = p_manifest.lib_spec();
+        let Some(sha256) = spec.sha256 else {
+            continue;
+        };
+
+        let cached = DllInfo::from_spec(&spec, &current.to_path_buf())?;
+        if cached.path.exists() && cache::sha256_hex(&fs::read(&cached.path)?) != sha256 {
+            debug!("{} changed in place", spec.url);
+            cached_download_lib(&DllInfo::from_spec(&spec, staging)?)?;
+        }
+    }
+
+    let layers = CacheLayers::new().writable(staging).read_only(current);
+    resolve(url, &layers, platform)?;
+
+    Ok(())
+}
+
+fn stage_and_switch(
+    update: &Update,
+    work_dir: &Path,
+    staging: &PathBuf,
+    generation: &Path,
+) -> Result<()> {
+    let current = cache::current_generation(work_dir)?;
+    let current_file = ManifestInfo::from_input(&update.url, &current)?.read_file()?;
+
+    // Only the platforms that have been used are downloaded.
+    let mut platforms = Vec::new();
+    for (platform, p_manifest) in &current_file.manifest.platforms {
+        if DllInfo::from_spec(&p_manifest.lib_spec(), &current)?
+            .path
+            .exists()
+        {
+            platforms.push(platform.clone());
+        }
+    }
+
+    cached_download_manifest(&ManifestInfo::from_input(&update.new_url, staging)?)?;
+
+    for platform in platforms {
+        debug!("staging update of {} for {}", update.new_url, platform);
+
+        if let Err(e) = stage_platform(&update.new_url, &current, staging, &platform) {
+            match e.downcast_ref() {
+                Some(ResolveError::PlatformNotSupported(_)) => {
+                    warn!("{} no longer supports {}", update.new_url, platform)
+                }
+                _ => return Err(e),
+            }
+        }
+    }
+
+    cache::snapshot(&current, generation)?;
+    switch_over(staging, generation, &update.new_url)?;
+
+    // The origin now leads to the new dllpack.
+    if let Some(origin) = &update.origin {
+        if update.new_url != update.url {
+            record_origin(
+                &update.new_url,
+                origin,
+                &CacheLayers::new().writable(generation),
+            )?;
+            fs::remove_file(origin_path(generation, &update.url))?;
+        }
+    }
+
+    cache::publish_generation(work_dir, generation, &current)
+}
+
+/// Downloads the new dependency graph of `update` next to the cached one,
+/// and then switches the cache of `work_dir` over to it.
+///
+/// The graph is downloaded for every platform of the dllpack that has been used,
+/// and staged as a new generation of the whole cache.
+/// The switch is atomic: concurrent loads see either the old or the new graph, never a mix.
+/// Processes that have the old version loaded are not affected.
+pub fn apply_update(update: &Update, work_dir: &Path) -> Result<()> {
+    cache::prepare(work_dir)?;
+    fs::create_dir_all(work_dir)?;
+
+    // The staging cache and the new generation are in `work_dir`,
+    // so files can be renamed and hard linked between them.
+    let staging = cache::temp_path(work_dir, "update");
+    let generation = cache::new_generation(work_dir)?;
+    let result = stage_and_switch(update, work_dir, &staging, &generation);
+
+    cache::forget_prepared(&staging);
+    for dir in [&staging, &generation] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{test_dir, write_app_and_dep, PLATFORM};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    fn dllpack(url: &str, sha256: &str) -> DllPackFile {
+        DllPackFile::from_str(&format!(
+            r#"{{
+                "spec-version": "1.0.0",
+                "manifest": {{
+                    "platforms": {{
+                        "x86_64-unknown-linux-gnu": {{ "url": "{}", "sha256": "{}" }}
+                    }}
+                }}
+            }}"#,
+            url, sha256
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_artifacts() {
+        let work_dir = PathBuf::from_str("/nonexistent").unwrap();
+
+        let current = dllpack("https://example.com/1.0.0/libadder.so", &"aa".repeat(32));
+        let same = dllpack("https://example.com/1.0.0/libadder.so", &"aa".repeat(32));
+        assert!(diff_artifacts(&current, &same, &work_dir)
+            .unwrap()
+            .is_empty());
+
+        let new = dllpack("https://example.com/1.1.0/libadder.so", &"bb".repeat(32));
+        assert_eq!(
+            diff_artifacts(&current, &new, &work_dir).unwrap(),
+            vec![ArtifactChange {
+                platform: "x86_64-unknown-linux-gnu".to_string(),
+                name: "libadder.so".to_string(),
+                old_url: Some(Url::from_str("https://example.com/1.0.0/libadder.so").unwrap()),
+                new_url: Some(Url::from_str("https://example.com/1.1.0/libadder.so").unwrap()),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_apply_update_in_place() {
+        let dir = test_dir("update-test");
+        let src_dir = dir.join("src");
+        let work_dir = dir.join("work");
+        fs::create_dir_all(&src_dir).unwrap();
+
+        let url = write_app_and_dep(&src_dir, "app v1");
+        resolve(&url, &work_dir, PLATFORM).unwrap();
+
+        // The library changes in place. The unchanged dependency is not downloaded again,
+        // so a change that its manifest does not declare is not picked up.
+        write_app_and_dep(&src_dir, "app v2");
+        fs::write(src_dir.join("dep.wasm"), "dep v2").unwrap();
+
+        let updates = check_updates(&work_dir).unwrap();
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].new_url, url);
+
+        apply_update(&updates[0], &work_dir).unwrap();
+
+        let (base, deps) = resolve(&url, &work_dir, PLATFORM).unwrap();
+        assert_eq!(fs::read_to_string(&base.path).unwrap(), "app v2");
+        assert_eq!(fs::read_to_string(&deps[0].path).unwrap(), "dep");
+        assert!(check_updates(&work_dir).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_apply_update_is_atomic() {
+        let dir = test_dir("update-atomic-test");
+        let src_dir = dir.join("src");
+        let work_dir = dir.join("work");
+        fs::create_dir_all(&src_dir).unwrap();
+
+        let url = write_app_and_dep(&src_dir, "app v1");
+        let (old_base, _) = resolve(&url, &work_dir, PLATFORM).unwrap();
+        write_app_and_dep(&src_dir, "app v2");
+        let update = check_updates(&work_dir).unwrap().remove(0);
+
+        // Every resolution sees the library that its own manifest declares,
+        // whether it runs before, during or after the switch.
+        let done = AtomicBool::new(false);
+        let seen = std::thread::scope(|s| {
+            let reader = s.spawn(|| {
+                let mut seen = BTreeSet::new();
+                while !done.load(Ordering::Relaxed) {
+                    let (base, _) = resolve(&url, &work_dir, PLATFORM).unwrap();
+                    let content = fs::read_to_string(&base.path).unwrap();
+                    assert_eq!(Some(cache::sha256_hex(content.as_bytes())), base.sha256);
+                    seen.insert(content);
+                }
+                seen
+            });
+
+            apply_update(&update, &work_dir).unwrap();
+            done.store(true, Ordering::Relaxed);
+            reader.join().unwrap()
+        });
+        assert!(seen.iter().all(|s| s == "app v1" || s == "app v2"));
+
+        // A graph resolved before the switch is left as it was.
+        assert_eq!(fs::read_to_string(&old_base.path).unwrap(), "app v1");
+        let (new_base, _) = resolve(&url, &work_dir, PLATFORM).unwrap();
+        assert_eq!(fs::read_to_string(&new_base.path).unwrap(), "app v2");
+
+        // The next update removes the generation before the previous one, here `work_dir` itself.
+        write_app_and_dep(&src_dir, "app v3");
+        apply_update(&check_updates(&work_dir).unwrap()[0], &work_dir).unwrap();
+        assert!(!old_base.path.exists());
+        assert_eq!(fs::read_to_string(&new_base.path).unwrap(), "app v2");
+        let (base, _) = resolve(&url, &work_dir, PLATFORM).unwrap();
+        assert_eq!(fs::read_to_string(&base.path).unwrap(), "app v3");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,203 @@
+//! Mirroring of dllpacks into self-contained directories.
+//!
+//! A vendored directory holds a dllpack with all of its dependencies for every platform,
+//! with every URL rewritten to a relative path, so it can be served or copied as is.
+
+use crate::cache::{self, CacheLocation};
+use crate::channel::follow_channels;
+use crate::dependency::Dependency;
+use crate::dllpack_file::map_urls;
+use crate::download::{cached_download_lib, cached_download_manifest, DllInfo, ManifestInfo};
+use anyhow::{anyhow, Result};
+use log::debug;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use url::Url;
+
+/// Mirrors the dllpack at `url`, together with all of its dependencies for **all platforms**,
+/// into `out_dir`. Everything is downloaded through the cache at `work_dir`.
+///
+/// The layout of `out_dir` is:
+/// ```text
+/// out_dir/
+///   <file name of url>    the root manifest
+///   <key>.dllpack         manifests of dependencies
+///   <key>/<name>          libraries
+/// ```
+/// where `<key>` is derived from the original URL.
+///
+/// All URLs in the written manifests are relative, so `out_dir` can be served from
+/// any static host, or loaded through a `file://` URL.
+///
+/// Returns the path of the root manifest.
+pub fn vendor(url: &Url, work_dir: &impl CacheLocation, out_dir: &PathBuf) -> Result<PathBuf> {
+    let root_name = url
+        .path_segments()
+        .and_then(|mut s| s.next_back())
+        .filter(|s| !s.is_empty())
+        .unwrap_or("root.dllpack");
+
+    vendor_with_root_name(url, work_dir, out_dir, root_name)
+}
+
+/// Implementation of `vendor`, with the file name of the root manifest given explicitly.
+pub(crate) fn vendor_with_root_name(
+    url: &Url,
+    work_dir: &impl CacheLocation,
+    out_dir: &PathBuf,
+    root_name: &str,
+) -> Result<PathBuf> {
+    let layers = work_dir.cache_layers().prepare()?;
+
+    fs::create_dir_all(out_dir)?;
+
+    // Relative locations in `out_dir` of everything written, by original URL.
+    let mut locations = BTreeMap::new();
+    let mut manifests = Vec::new();
+
+    let mut visited = BTreeSet::from([url.clone()]);
+    let mut queue = VecDeque::from([url.clone()]);
+
+    while let Some(m_url) = queue.pop_front() {
+        // Channels are pinned to their current target in the vendored copy.
+        let concrete_url = follow_channels(&m_url, &layers)?;
+        let info = ManifestInfo::from_layers(&concrete_url, &layers)?;
+        cached_download_manifest(&info)?;
+        let mut file = info.read_file()?;
+
+        for p_manifest in file.manifest.platforms.values() {
+            let mut libs = vec![p_manifest.lib_spec()];
+
+            for dep in &p_manifest.dependencies {
+                match dep {
+                    Dependency::RawLib { .. } => libs.push(dep.lib_spec().unwrap()),
+                    Dependency::DllPack { url, .. } => {
+                        if visited.insert(url.clone()) {
+                            queue.push_back(url.clone());
+                        }
+                    }
+                }
+            }
+
+            for spec in libs {
+                if locations.contains_key(spec.url.as_str()) {
+                    continue;
+                }
+
+                let dll_info = DllInfo::from_layers(&spec, &layers)?;
+                cached_download_lib(&dll_info)?;
+
+                let key = cache::url_key(spec.url);
+                debug!("vendoring {} as {}/{}", spec.url, key, dll_info.name);
+
+                fs::create_dir_all(out_dir.join(&key))?;
+                fs::copy(&dll_info.path, out_dir.join(&key).join(&dll_info.name))?;
+
+                // The name is a file name, which may contain characters that are special in URLs.
+                let location = format!("{}/{}", key, urlencoding::encode(&dll_info.name));
+                locations.insert(spec.url.to_string(), location);
+            }
+        }
+
+        // Libraries are copied from the cache, where they are stored decompressed.
+        for p_manifest in file.manifest.platforms.values_mut() {
+            p_manifest.compression = None;
+            p_manifest.compressed_sha256 = None;
+
+            for dep in &mut p_manifest.dependencies {
+                if let Dependency::RawLib {
+                    compression,
+                    compressed_sha256,
+                    ..
+                } = dep
+                {
+                    *compression = None;
+                    *compressed_sha256 = None;
+                }
+            }
+        }
+
+        let location = if &m_url == url {
+            root_name.to_string()
+        } else {
+            format!("{}.dllpack", cache::url_key(&m_url))
+        };
+        locations.insert(m_url.to_string(), location.clone());
+        manifests.push((location, file));
+    }
+
+    // Write the manifests with all URLs rewritten to point into `out_dir`.
+    for (location, file) in manifests {
+        let mut value = serde_json::to_value(&file)?;
+        map_urls(&mut value, |u| {
+            locations
+                .get(u)
+                .cloned()
+                .ok_or(anyhow!("{} was not vendored", u))
+        })?;
+
+        debug!("writing manifest {}", location);
+        fs::write(
+            out_dir.join(location),
+            serde_json::to_string_pretty(&value)?,
+        )?;
+    }
+
+    Ok(out_dir.join(root_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dllpack_file::DllPackFile;
+    use crate::test_fixtures::{test_dir, write_dep, write_dllpack, PLATFORM};
+    use serde_json::json;
+
+    #[test]
+    fn test_vendor() {
+        let dir = test_dir("vendor");
+        let (src_dir, work_dir, out_dir) = (dir.join("src"), dir.join("work"), dir.join("out"));
+        fs::create_dir_all(&src_dir).unwrap();
+
+        write_dllpack(
+            &src_dir,
+            "app.dllpack",
+            json!({
+                "url": "my%20app.wasm",
+                "dependencies": [
+                    { "type": "dllpack", "url": "dep.dllpack" },
+                    { "type": "rawlib", "url": "raw.wasm", "name": "raw #1.wasm" }
+                ]
+            }),
+        );
+        write_dep(&src_dir);
+        for name in ["my app.wasm", "raw.wasm"] {
+            fs::write(src_dir.join(name), name).unwrap();
+        }
+
+        let url = Url::from_file_path(src_dir.join("app.dllpack")).unwrap();
+        let root = vendor(&url, &work_dir, &out_dir).unwrap();
+        assert_eq!(root, out_dir.join("app.dllpack"));
+
+        // Every URL of the vendored manifests points to a file in `out_dir`.
+        let root_url = Url::from_file_path(&root).unwrap();
+        let app = DllPackFile::from_file_with_base(&root, &root_url).unwrap();
+        let p_manifest = &app.manifest.platforms[PLATFORM];
+        let read = |url: &Url| fs::read_to_string(url.to_file_path().unwrap()).unwrap();
+
+        assert_eq!(read(&p_manifest.url), "my app.wasm");
+        let Dependency::DllPack { url: dep_url, .. } = &p_manifest.dependencies[0] else {
+            panic!("expected a dllpack dependency");
+        };
+        let Dependency::RawLib { url: raw_url, .. } = &p_manifest.dependencies[1] else {
+            panic!("expected a rawlib dependency");
+        };
+        assert_eq!(read(raw_url), "raw.wasm");
+
+        let dep = DllPackFile::from_str_with_base(&read(dep_url), dep_url).unwrap();
+        assert_eq!(read(&dep.manifest.platforms[PLATFORM].url), "dep");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,634 @@
+//! Configuration of the WASI environment of wasm libraries.
+//!
+//! By default, a wasm library inherits the environment variables and the standard streams
+//! of the host, and has full access to the file system.
+//! One possible way to ensure security would be to restrict these,
+//! but since dllpack can load native libraries, such restrictions would not be very meaningful
+//! in practice. [`WasiConfig`] is rather meant for controlling what a library sees and
+//! where its output goes.
+
+#[cfg(windows)]
+use crate::fs_utils::get_available_drives;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use log::{debug, log, Level};
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use url::Url;
+use wasmtime_wasi::pipe::{ClosedInputStream, MemoryInputPipe, MemoryOutputPipe, SinkOutputStream};
+use wasmtime_wasi::preview1::WasiP1Ctx;
+use wasmtime_wasi::{
+    async_trait, DirPerms, FilePerms, HostOutputStream, OutputFile, StdoutStream, StreamResult,
+    Subscribe, WasiCtx, WasiCtxBuilder,
+};
+
+/// The standard input of a wasm library.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum InputStream {
+    /// The standard input of the host.
+    #[default]
+    Inherit,
+    /// An empty input.
+    Null,
+    /// The given bytes.
+    Bytes(Vec<u8>),
+    /// The content of a file, read when the library is loaded.
+    File(PathBuf),
+}
+
+/// The standard output or error of a wasm library.
+#[derive(Debug, Clone, Default)]
+pub enum OutputStream {
+    /// The corresponding stream of the host.
+    #[default]
+    Inherit,
+    /// Discards the output.
+    Null,
+    /// An in-memory pipe; the output can be read with [`MemoryOutputPipe::contents`]
+    /// through another reference to the pipe.
+    /// Pipes are equal if they are the same pipe.
+    Pipe(Arc<MemoryOutputPipe>),
+    /// A file, created (or truncated) when the library is loaded.
+    File(PathBuf),
+    /// Log records of the given level, one per line, with the target `dll_pack::guest`.
+    /// Each record starts with the URL of the library and the name of the stream.
+    /// Overlong lines are split, and an incomplete last line is logged when the library is dropped.
+    Log(Level),
+    /// A buffer of the output of each call, returned by
+    /// [`Function::try_call_captured`](crate::load::Function::try_call_captured).
+    /// Output beyond [`CAPTURE_LIMIT`] bytes per call is discarded.
+    Capture,
+}
+
+impl PartialEq for OutputStream {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (OutputStream::Inherit, OutputStream::Inherit)
+            | (OutputStream::Null, OutputStream::Null)
+            | (OutputStream::Capture, OutputStream::Capture) => true,
+            (OutputStream::Pipe(a), OutputStream::Pipe(b)) => Arc::ptr_eq(a, b),
+            (OutputStream::File(a), OutputStream::File(b)) => a == b,
+            (OutputStream::Log(a), OutputStream::Log(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for OutputStream {}
+
+/// The target of log records of guest output.
+const GUEST_LOG_TARGET: &str = "dll_pack::guest";
+
+/// The maximum number of bytes of standard output or error captured per call.
+/// The rest of the output of the call is discarded.
+pub const CAPTURE_LIMIT: usize = 16 * 1024 * 1024;
+
+/// The maximum length of a logged line of guest output.
+/// Longer lines are split into several log records.
+const LOG_LINE_LIMIT: usize = 64 * 1024;
+
+/// The number of bytes a guest may write at once.
+/// Writes are never blocked, as output beyond the limits is discarded or split.
+const WRITE_PERMIT: usize = 64 * 1024;
+
+/// A standard output or error of a guest that is logged line by line.
+#[derive(Debug)]
+struct GuestLog {
+    level: Level,
+    url: Url,
+    stream: &'static str,
+    /// The last incomplete line.
+    line: Mutex<Vec<u8>>,
+}
+
+impl GuestLog {
+    fn emit(&self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        log!(
+            target: GUEST_LOG_TARGET,
+            self.level,
+            "{} {}: {}",
+            self.url,
+            self.stream,
+            line.trim_end_matches(['\n', '\r'])
+        );
+    }
+}
+
+/// The last line is logged when the WASI context is dropped, even if it is incomplete.
+///
+/// It is not logged on flush, as WASI preview1 flushes after every write
+/// and a line is often written in several pieces.
+impl Drop for GuestLog {
+    fn drop(&mut self) {
+        let line = std::mem::take(self.line.get_mut().unwrap());
+        if !line.is_empty() {
+            self.emit(&line);
+        }
+    }
+}
+
+/// A standard output or error of a guest that is logged or captured.
+/// The stream is cloned for every access of the guest, so its state is shared.
+#[derive(Debug, Clone)]
+enum GuestOutput {
+    Log(Arc<GuestLog>),
+    /// The output since the start of the current call.
+    Capture(Arc<Mutex<Vec<u8>>>),
+}
+
+impl StdoutStream for GuestOutput {
+    fn stream(&self) -> Box<dyn HostOutputStream> {
+        Box::new(self.clone())
+    }
+
+    fn isatty(&self) -> bool {
+        false
+    }
+}
+
+impl HostOutputStream for GuestOutput {
+    fn write(&mut self, bytes: Bytes) -> StreamResult<()> {
+        match self {
+            GuestOutput::Log(log) => {
+                let mut line = log.line.lock().unwrap();
+                line.extend_from_slice(&bytes);
+
+                while let Some(end) = line.iter().position(|&b| b == b'\n') {
+                    let complete: Vec<u8> = line.drain(..=end).collect();
+                    log.emit(&complete);
+                }
+                while line.len() >= LOG_LINE_LIMIT {
+                    let part: Vec<u8> = line.drain(..LOG_LINE_LIMIT).collect();
+                    log.emit(&part);
+                }
+            }
+            GuestOutput::Capture(buffer) => {
+                let mut buffer = buffer.lock().unwrap();
+                let room = CAPTURE_LIMIT.saturating_sub(buffer.len());
+                if bytes.len() > room {
+                    debug!("discarding guest output beyond {} bytes", CAPTURE_LIMIT);
+                }
+                buffer.extend_from_slice(&bytes[..bytes.len().min(room)]);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> StreamResult<()> {
+        Ok(())
+    }
+
+    fn check_write(&mut self) -> StreamResult<usize> {
+        Ok(WRITE_PERMIT)
+    }
+}
+
+#[async_trait]
+impl Subscribe for GuestOutput {
+    async fn ready(&mut self) {}
+}
+
+/// The buffers of the captured standard output and error of a wasm library.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Captures {
+    stdout: Option<Arc<Mutex<Vec<u8>>>>,
+    stderr: Option<Arc<Mutex<Vec<u8>>>>,
+}
+
+impl Captures {
+    fn take(buffer: &Option<Arc<Mutex<Vec<u8>>>>) -> Vec<u8> {
+        buffer
+            .as_ref()
+            .map(|b| std::mem::take(&mut *b.lock().unwrap()))
+            .unwrap_or_default()
+    }
+
+    /// Takes the captured standard output and error.
+    pub(crate) fn take_all(&self) -> (Vec<u8>, Vec<u8>) {
+        (Self::take(&self.stdout), Self::take(&self.stderr))
+    }
+}
+
+/// A host directory made accessible to a wasm library.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Preopen {
+    pub host_path: PathBuf,
+    /// The path of the directory as seen by the guest.
+    pub guest_path: String,
+    pub read_only: bool,
+}
+
+/// The WASI environment of a wasm library: its arguments, environment variables,
+/// accessible directories and standard streams.
+///
+/// The default inherits the environment variables and the standard streams of the host,
+/// and gives full access to the file system.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WasiConfig {
+    args: Vec<String>,
+    /// The environment variables, or `None` to inherit them.
+    env: Option<Vec<(String, String)>>,
+    /// The accessible directories, or `None` for the whole file system.
+    preopens: Option<Vec<Preopen>>,
+    stdin: InputStream,
+    stdout: OutputStream,
+    stderr: OutputStream,
+}
+
+impl WasiConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends an argument. Note that the first argument is conventionally the program name.
+    pub fn arg(mut self, arg: impl Into<String>) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Appends arguments.
+    pub fn args(mut self, args: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Sets an environment variable.
+    /// Once one is set, the environment variables of the host are no longer inherited.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env
+            .get_or_insert_with(Vec::new)
+            .push((key.into(), value.into()));
+        self
+    }
+
+    /// Makes the environment empty, instead of inheriting it from the host.
+    pub fn clear_env(mut self) -> Self {
+        self.env = Some(Vec::new());
+        self
+    }
+
+    /// Makes a host directory readable and writable at `guest_path`.
+    /// Once a directory is added, the rest of the file system is no longer accessible.
+    pub fn preopen_dir(self, host_path: impl Into<PathBuf>, guest_path: impl Into<String>) -> Self {
+        self.preopen(host_path.into(), guest_path.into(), false)
+    }
+
+    /// Makes a host directory readable at `guest_path`.
+    /// Once a directory is added, the rest of the file system is no longer accessible.
+    pub fn preopen_dir_read_only(
+        self,
+        host_path: impl Into<PathBuf>,
+        guest_path: impl Into<String>,
+    ) -> Self {
+        self.preopen(host_path.into(), guest_path.into(), true)
+    }
+
+    fn preopen(mut self, host_path: PathBuf, guest_path: String, read_only: bool) -> Self {
+        self.preopens.get_or_insert_with(Vec::new).push(Preopen {
+            host_path,
+            guest_path,
+            read_only,
+        });
+        self
+    }
+
+    /// Makes no directory accessible.
+    pub fn no_preopens(mut self) -> Self {
+        self.preopens = Some(Vec::new());
+        self
+    }
+
+    pub fn stdin(mut self, stdin: InputStream) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    pub fn stdout(mut self, stdout: OutputStream) -> Self {
+        self.stdout = stdout;
+        self
+    }
+
+    pub fn stderr(mut self, stderr: OutputStream) -> Self {
+        self.stderr = stderr;
+        self
+    }
+
+    /// Builds a WASI preview1 context for the library at `url`,
+    /// opening the files and directories of the configuration.
+    pub(crate) fn build_p1(&self, url: &Url) -> Result<(WasiP1Ctx, Captures)> {
+        let (mut builder, captures) = self.builder(url)?;
+        Ok((builder.build_p1(), captures))
+    }
+
+    /// Builds a WASI preview2 context for the component at `url`, like [`Self::build_p1`].
+    pub(crate) fn build_p2(&self, url: &Url) -> Result<(WasiCtx, Captures)> {
+        let (mut builder, captures) = self.builder(url)?;
+        Ok((builder.build(), captures))
+    }
+
+    fn builder(&self, url: &Url) -> Result<(WasiCtxBuilder, Captures)> {
+        let mut builder = WasiCtxBuilder::new();
+
+        builder.args(&self.args);
+
+        match &self.env {
+            None => {
+                builder.inherit_env();
+            }
+            Some(env) => {
+                builder.envs(env);
+            }
+        }
+
+        match &self.preopens {
+            None => pre_open_all(&mut builder)?,
+            Some(preopens) => {
+                for preopen in preopens {
+                    let (dir_perms, file_perms) = if preopen.read_only {
+                        (DirPerms::READ, FilePerms::READ)
+                    } else {
+                        (DirPerms::all(), FilePerms::all())
+                    };
+                    builder
+                        .preopened_dir(
+                            &preopen.host_path,
+                            &preopen.guest_path,
+                            dir_perms,
+                            file_perms,
+                        )
+                        .map_err(|e| {
+                            anyhow!(
+                                "Failed to open {} for WASI: {}",
+                                preopen.host_path.display(),
+                                e
+                            )
+                        })?;
+                }
+            }
+        }
+
+        match &self.stdin {
+            InputStream::Inherit => {
+                builder.inherit_stdin();
+            }
+            InputStream::Null => {
+                builder.stdin(ClosedInputStream);
+            }
+            InputStream::Bytes(bytes) => {
+                builder.stdin(MemoryInputPipe::new(bytes.clone()));
+            }
+            InputStream::File(path) => {
+                let content = fs::read(path)
+                    .map_err(|e| anyhow!("Failed to read {} for WASI: {}", path.display(), e))?;
+                builder.stdin(MemoryInputPipe::new(content));
+            }
+        }
+
+        let mut captures = Captures::default();
+        let log = |level: &Level, stream| {
+            GuestOutput::Log(Arc::new(GuestLog {
+                level: *level,
+                url: url.clone(),
+                stream,
+                line: Mutex::new(Vec::new()),
+            }))
+        };
+        let capture = |buffer: &mut Option<Arc<Mutex<Vec<u8>>>>| {
+            GuestOutput::Capture(buffer.insert(Arc::default()).clone())
+        };
+
+        match &self.stdout {
+            OutputStream::Inherit => builder.inherit_stdout(),
+            OutputStream::Null => builder.stdout(SinkOutputStream),
+            OutputStream::Pipe(pipe) => builder.stdout(MemoryOutputPipe::clone(pipe)),
+            OutputStream::File(path) => builder.stdout(OutputFile::new(create_output(path)?)),
+            OutputStream::Log(level) => builder.stdout(log(level, "stdout")),
+            OutputStream::Capture => builder.stdout(capture(&mut captures.stdout)),
+        };
+
+        match &self.stderr {
+            OutputStream::Inherit => builder.inherit_stderr(),
+            OutputStream::Null => builder.stderr(SinkOutputStream),
+            OutputStream::Pipe(pipe) => builder.stderr(MemoryOutputPipe::clone(pipe)),
+            OutputStream::File(path) => builder.stderr(OutputFile::new(create_output(path)?)),
+            OutputStream::Log(level) => builder.stderr(log(level, "stderr")),
+            OutputStream::Capture => builder.stderr(capture(&mut captures.stderr)),
+        };
+
+        Ok((builder, captures))
+    }
+}
+
+/// Creates (or truncates) the file of a standard output or error.
+fn create_output(path: &Path) -> Result<File> {
+    File::create(path).map_err(|e| anyhow!("Failed to create {} for WASI: {}", path.display(), e))
+}
+
+#[cfg(unix)]
+fn pre_open_all(wasi_ctx_builder: &mut WasiCtxBuilder) -> Result<()> {
+    wasi_ctx_builder.preopened_dir("/", "/", DirPerms::all(), FilePerms::all())?;
+
+    Ok(())
+}
+
+#[cfg(windows)]
+fn pre_open_all(wasi_ctx_builder: &mut WasiCtxBuilder) -> Result<()> {
+    // Note that it cannot handle, for example, drives connected after the context has been created.
+    // Some alternative solution is needed for this.
+    for drive in get_available_drives() {
+        wasi_ctx_builder.preopened_dir(
+            format!("{}:\\", drive.to_uppercase()),
+            format!("/{}", drive.to_lowercase()),
+            DirPerms::all(),
+            FilePerms::all(),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::load::tests::load_test_wasm;
+    use crate::load::{Library, WasmOptions};
+    use wasmtime::{Engine, Linker, Module, Store};
+    use wasmtime_wasi::preview1;
+
+    /// A module that exports `hello: () -> ()`, which writes "hi\n" to the standard output.
+    const HELLO_WASM: &[u8] = &[
+        0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+        0x01, 0x0c, 0x02, // type section
+        0x60, 0x04, 0x7f, 0x7f, 0x7f, 0x7f, 0x01, 0x7f, // (i32, i32, i32, i32) -> i32
+        0x60, 0x00, 0x00, // () -> ()
+        0x02, 0x23, 0x01, 0x16, // import section
+        b'w', b'a', b's', b'i', b'_', b's', b'n', b'a', b'p', b's', b'h', b'o', b't', b'_', b'p',
+        b'r', b'e', b'v', b'i', b'e', b'w', b'1', // "wasi_snapshot_preview1"
+        0x08, b'f', b'd', b'_', b'w', b'r', b'i', b't', b'e', 0x00, 0x00, // "fd_write"
+        0x03, 0x02, 0x01, 0x01, // function section
+        0x05, 0x03, 0x01, 0x00, 0x01, // memory section: 1 page
+        0x07, 0x12, 0x02, // export section
+        0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00, // "memory"
+        0x05, b'h', b'e', b'l', b'l', b'o', 0x00, 0x01, // "hello"
+        0x0a, 0x0f, 0x01, 0x0d, 0x00, // code section
+        0x41, 0x01, 0x41, 0x00, 0x41, 0x01, 0x41, 0x08, // 1, 0, 1, 8
+        0x10, 0x00, 0x1a, 0x0b, // call fd_write, drop
+        0x0b, 0x16, 0x02, // data section
+        0x00, 0x41, 0x00, 0x0b, 0x08, 0x10, 0x00, 0x00, 0x00, 0x03, 0x00, 0x00, 0x00, // iovec
+        0x00, 0x41, 0x10, 0x0b, 0x03, b'h', b'i', b'\n', // "hi\n"
+    ];
+
+    #[test]
+    fn test_stdout_pipe() {
+        let stdout = Arc::new(MemoryOutputPipe::new(1024));
+        let config = WasiConfig::new()
+            .clear_env()
+            .no_preopens()
+            .stdout(OutputStream::Pipe(stdout.clone()));
+
+        let engine = Engine::default();
+        let module = Module::from_binary(&engine, HELLO_WASM).unwrap();
+        let mut linker = Linker::new(&engine);
+        preview1::add_to_linker_sync(&mut linker, |t| t).unwrap();
+        let url = Url::parse("https://example.com/hello.dllpack").unwrap();
+        let (wasi, _) = config.build_p1(&url).unwrap();
+        let mut store = Store::new(&engine, wasi);
+        let instance = linker.instantiate(&mut store, &module).unwrap();
+
+        let hello = instance
+            .get_typed_func::<(), ()>(&mut store, "hello")
+            .unwrap();
+        hello.call(&mut store, ()).unwrap();
+
+        assert_eq!(&stdout.contents()[..], b"hi\n");
+    }
+
+    /// Loads [`HELLO_WASM`], writing `hello` instead of "hi\n", with the given standard output.
+    fn load_hello(name: &str, hello: &[u8; 3], stdout: OutputStream) -> Library {
+        let mut wasm = HELLO_WASM.to_vec();
+        let len = wasm.len();
+        wasm[len - 3..].copy_from_slice(hello);
+
+        let options = WasmOptions {
+            wasi: WasiConfig::new().no_preopens().stdout(stdout),
+            ..Default::default()
+        };
+        load_test_wasm(name, &wasm, "wasm32-wasip1", &options)
+    }
+
+    #[test]
+    fn test_try_call_captured() {
+        let mut library = load_hello("captured", b"hi\n", OutputStream::Capture);
+        let hello = library.get_function::<(), ()>("hello").unwrap();
+
+        let output = hello.try_call_captured(&mut library, ());
+        assert!(output.result.is_ok());
+        assert_eq!(output.stdout, b"hi\n");
+        assert!(output.stderr.is_empty());
+
+        // Only the output of the last call is returned.
+        hello.call(&mut library, ());
+        let output = hello.try_call_captured(&mut library, ());
+        assert_eq!(output.stdout, b"hi\n");
+    }
+
+    #[test]
+    fn test_capture_limit() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let mut output = GuestOutput::Capture(buffer.clone());
+
+        output
+            .write(Bytes::from(vec![b'a'; CAPTURE_LIMIT - 1]))
+            .unwrap();
+        output.write(Bytes::from_static(b"bc")).unwrap();
+        let buffer = buffer.lock().unwrap();
+        assert_eq!(buffer.len(), CAPTURE_LIMIT);
+        assert_eq!(buffer.last(), Some(&b'b'));
+    }
+
+    /// Collects the log records of guest output.
+    struct GuestLogger(Mutex<Vec<String>>);
+
+    impl log::Log for GuestLogger {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.target() == GUEST_LOG_TARGET
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) {
+                self.0.lock().unwrap().push(record.args().to_string());
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    static GUEST_LOGGER: GuestLogger = GuestLogger(Mutex::new(Vec::new()));
+
+    /// The records logged for the dllpack `name`.
+    fn guest_records(name: &str) -> Vec<String> {
+        let suffix = format!("/{}.dllpack stdout", name);
+        GUEST_LOGGER
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|r| r.contains(&suffix))
+            .map(|r| r[r.find(&suffix).unwrap() + suffix.len()..].to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_log_output() {
+        let _ = log::set_logger(&GUEST_LOGGER);
+        log::set_max_level(log::LevelFilter::Trace);
+
+        let mut library = load_hello("log", b"hi\n", OutputStream::Log(Level::Info));
+        let hello = library.get_function::<(), ()>("hello").unwrap();
+        hello.call(&mut library, ());
+        hello.call(&mut library, ());
+        assert_eq!(guest_records("log"), vec![": hi", ": hi"]);
+
+        // An incomplete line is logged once the library is dropped.
+        let mut library = load_hello("log-partial", b"hi!", OutputStream::Log(Level::Info));
+        let hello = library.get_function::<(), ()>("hello").unwrap();
+        hello.call(&mut library, ());
+        hello.call(&mut library, ());
+        assert!(guest_records("log-partial").is_empty());
+        drop(library);
+        assert_eq!(guest_records("log-partial"), vec![": hi!hi!"]);
+    }
+
+    #[test]
+    fn test_config_eq() {
+        let pipe = Arc::new(MemoryOutputPipe::new(1024));
+        let config = WasiConfig::new()
+            .arg("hello")
+            .stdout(OutputStream::Pipe(pipe.clone()));
+
+        assert_eq!(config, config.clone());
+        assert_ne!(
+            config,
+            WasiConfig::new()
+                .arg("hello")
+                .stdout(OutputStream::Pipe(Arc::new(MemoryOutputPipe::new(1024))))
+        );
+    }
+
+    #[test]
+    fn test_missing_file() {
+        let path = std::env::temp_dir().join(format!("dll-pack-missing-{}/in", std::process::id()));
+        let url = Url::parse("https://example.com/hello.dllpack").unwrap();
+
+        let config = WasiConfig::new().stdin(InputStream::File(path.clone()));
+        let e = config.build_p1(&url).err().unwrap();
+        assert!(e.to_string().contains(&path.display().to_string()));
+
+        let config = WasiConfig::new().stdout(OutputStream::File(path.clone()));
+        let e = config.build_p1(&url).err().unwrap();
+        assert!(e.to_string().contains(&path.display().to_string()));
+    }
+}
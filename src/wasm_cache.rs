@@ -0,0 +1,478 @@
+//! The wasmtime engine shared by all wasm libraries, and caches of compiled modules and components.
+//!
+//! Compiled modules are cached on disk next to the artifact, keyed by the wasm binary and
+//! the engine, and in memory as [`InstancePre`]s,
+//! so that another instance of an already loaded library only costs an instantiation.
+//! Components are cached the same way.
+
+use crate::cache;
+use crate::download::DllInfo;
+use crate::load::WasmState;
+use anyhow::{anyhow, Result};
+use log::{debug, trace};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::{LazyLock, Mutex, Once};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use wasmtime::component::{self, Component};
+use wasmtime::{Config, Engine, ExternType, InstancePre, Linker, Module};
+use wasmtime_wasi::{preview1, WasiCtxBuilder};
+
+/// Identifies the content of an artifact file without reading it.
+/// An artifact replaced in the cache (for example, by an update) gets a new key.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ArtifactKey {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+impl ArtifactKey {
+    fn of(path: &PathBuf) -> Result<Self> {
+        let metadata = fs::metadata(path)?;
+
+        Ok(Self {
+            path: path.clone(),
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+        })
+    }
+}
+
+/// The engines, by whether they consume fuel.
+/// Fuel metering slows down all code compiled by an engine, so it has its own engine.
+static ENGINES: LazyLock<Mutex<HashMap<bool, Engine>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// A module linked for an instance.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct ModuleKey {
+    artifact: ArtifactKey,
+    /// Whether it is compiled to consume fuel.
+    consume_fuel: bool,
+    /// Whether it is linked with WASI.
+    wasi: bool,
+}
+
+/// The linked modules.
+static INSTANCE_PRES: LazyLock<Mutex<HashMap<ModuleKey, InstancePre<WasmState>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The linked components.
+static COMPONENT_PRES: LazyLock<Mutex<HashMap<ModuleKey, component::InstancePre<WasmState>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// The engine shared by all wasm libraries in this process that do (or do not) consume fuel.
+pub(crate) fn engine(consume_fuel: bool) -> Result<Engine> {
+    let mut engines = ENGINES.lock().unwrap();
+
+    if let Some(engine) = engines.get(&consume_fuel) {
+        return Ok(engine.clone());
+    }
+
+    let mut config = Config::default();
+    // See https://github.com/bytecodealliance/wasmtime/issues/8897
+    #[cfg(unix)]
+    config.native_unwind_info(false);
+    // Used for timeouts of calls, see `epoch_deadline`.
+    config.epoch_interruption(true);
+    config.consume_fuel(consume_fuel);
+
+    let engine = Engine::new(&config)?;
+    engines.insert(consume_fuel, engine.clone());
+
+    Ok(engine)
+}
+
+/// The interval at which the epochs of the shared engines are incremented while timeouts are in use.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// An epoch deadline that is never reached, for calls without a timeout.
+pub(crate) const NO_EPOCH_DEADLINE: u64 = u64::MAX / 2;
+
+static EPOCH_TICKER: Once = Once::new();
+
+/// Returns the epoch deadline (in ticks beyond the current epoch) of a call with `timeout`,
+/// starting the background thread that ticks the epochs of the shared engines if necessary.
+///
+/// As the current tick may be partially elapsed, the call may run up to one tick longer.
+pub(crate) fn epoch_deadline(timeout: Duration) -> u64 {
+    EPOCH_TICKER.call_once(|| {
+        debug!("starting the epoch ticker");
+        thread::Builder::new()
+            .name("dll-pack-epoch-ticker".to_string())
+            .spawn(|| loop {
+                thread::sleep(EPOCH_TICK);
+                for engine in ENGINES.lock().unwrap().values() {
+                    engine.increment_epoch();
+                }
+            })
+            .expect("failed to spawn the epoch ticker thread");
+    });
+
+    let ticks = timeout.as_nanos().div_ceil(EPOCH_TICK.as_nanos());
+    u64::try_from(ticks)
+        .unwrap_or(NO_EPOCH_DEADLINE)
+        .clamp(1, NO_EPOCH_DEADLINE)
+        + 1
+}
+
+/// The key of a compiled module: the SHA-256 of its kind (see [`Compiled::KIND`]), the wasm binary
+/// and the engine's compatibility hash, which covers the wasmtime version and the engine configuration.
+///
+/// The compatibility hash is hashed with the standard library's `DefaultHasher`,
+/// whose algorithm may change between Rust versions; that only causes a rebuild.
+fn module_key(engine: &Engine, kind: &str, wasm_bin: &[u8]) -> String {
+    let mut hasher = DefaultHasher::new();
+    engine.precompile_compatibility_hash().hash(&mut hasher);
+
+    let mut key = Sha256::new();
+    key.update(format!("{}\n", kind).as_bytes());
+    key.update(cache::sha256_hex(wasm_bin).as_bytes());
+    key.update(hasher.finish().to_le_bytes());
+
+    cache::hex(&key.finalize())
+}
+
+/// A compiled core module or component.
+trait Compiled: Sized {
+    /// The kind of the compiled code, as a module cache of one kind cannot be read as the other.
+    const KIND: &'static str;
+
+    fn compile(engine: &Engine, wasm_bin: &[u8]) -> Result<Self>;
+
+    /// # Safety
+    /// `serialized` must come from [`Compiled::serialize`] of a compatible engine.
+    unsafe fn deserialize(engine: &Engine, serialized: &[u8]) -> Result<Self>;
+
+    fn serialize(&self) -> Result<Vec<u8>>;
+}
+
+impl Compiled for Module {
+    const KIND: &'static str = "module";
+
+    fn compile(engine: &Engine, wasm_bin: &[u8]) -> Result<Self> {
+        Module::from_binary(engine, wasm_bin)
+    }
+
+    unsafe fn deserialize(engine: &Engine, serialized: &[u8]) -> Result<Self> {
+        Module::deserialize(engine, serialized)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Module::serialize(self)
+    }
+}
+
+impl Compiled for Component {
+    const KIND: &'static str = "component";
+
+    fn compile(engine: &Engine, wasm_bin: &[u8]) -> Result<Self> {
+        Component::from_binary(engine, wasm_bin)
+    }
+
+    unsafe fn deserialize(engine: &Engine, serialized: &[u8]) -> Result<Self> {
+        Component::deserialize(engine, serialized)
+    }
+
+    fn serialize(&self) -> Result<Vec<u8>> {
+        Component::serialize(self)
+    }
+}
+
+/// Loads a module from the module cache on disk, if it was compiled with `key`.
+///
+/// The cache file is the key on a line, followed by the serialized module.
+fn read_cached_module<T: Compiled>(engine: &Engine, cache_path: &Path, key: &str) -> Option<T> {
+    let content = fs::read(cache_path).ok()?;
+
+    let Some(serialized) = content
+        .strip_prefix(key.as_bytes())
+        .and_then(|rest| rest.strip_prefix(b"\n"))
+    else {
+        debug!("module cache is stale: {}", cache_path.display());
+        return None;
+    };
+
+    // Safety: the module was serialized by `Compiled::serialize` of a compatible engine,
+    // as the key (which includes the engine's compatibility hash) matches.
+    match unsafe { T::deserialize(engine, serialized) } {
+        Ok(module) => Some(module),
+        Err(e) => {
+            debug!(
+                "could not deserialize module cache {}: {}",
+                cache_path.display(),
+                e
+            );
+            None
+        }
+    }
+}
+
+/// Compiles the module (or component) of `base_info`, or loads it from the module cache on disk.
+/// A missing, stale or broken cache is rebuilt.
+fn load_module<T: Compiled>(engine: &Engine, base_info: &DllInfo, consume_fuel: bool) -> Result<T> {
+    let cache_path = base_info.module_cache_path(consume_fuel);
+
+    let wasm_bin = fs::read(&base_info.path)?;
+    let key = module_key(engine, T::KIND, &wasm_bin);
+
+    // Use cached module if available.
+    if let Some(module) = read_cached_module(engine, &cache_path, &key) {
+        debug!(
+            "{}: loaded from cache: {}",
+            base_info.name,
+            cache_path.display()
+        );
+
+        return Ok(module);
+    }
+
+    debug!(
+        "{}: manual loading: {}",
+        base_info.name,
+        base_info.path.display()
+    );
+
+    let module = T::compile(engine, wasm_bin.as_slice())?;
+
+    let mut cache_bin = format!("{}\n", key).into_bytes();
+    cache_bin.extend(module.serialize()?);
+
+    trace!("serializing to cache: {}", cache_path.display());
+
+    // Written aside and renamed, so that other processes never read a partial cache.
+    // The library may come from a read-only cache layer, so a failed write is not fatal.
+    let cache_dir = cache_path.parent().unwrap();
+    let temp_path = cache::temp_path(cache_dir, "module-cache");
+    let written = fs::create_dir_all(cache_dir)
+        .and_then(|_| fs::write(&temp_path, cache_bin))
+        .and_then(|_| fs::rename(&temp_path, &cache_path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&temp_path);
+        debug!(
+            "could not write module cache {}: {}",
+            cache_path.display(),
+            e
+        );
+    }
+
+    Ok(module)
+}
+
+/// The module of the WASI functions provided to guests.
+const WASI_MODULE: &str = "wasi_snapshot_preview1";
+
+/// Fails with a list of the imports of `module` that cannot be satisfied,
+/// which are all imports but the WASI functions if `wasi` is true.
+fn check_imports(module: &Module, wasi: bool) -> Result<()> {
+    let unsatisfied: Vec<_> = module
+        .imports()
+        .filter(|import| !(wasi && import.module() == WASI_MODULE))
+        .map(|import| {
+            let kind = match import.ty() {
+                ExternType::Func(_) => "function",
+                ExternType::Global(_) => "global",
+                ExternType::Table(_) => "table",
+                ExternType::Memory(_) => "memory",
+            };
+            format!("{} `{}::{}`", kind, import.module(), import.name())
+        })
+        .collect();
+
+    if unsatisfied.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "the module has imports that cannot be satisfied{}:\n  {}",
+        if wasi { "" } else { " without WASI" },
+        unsatisfied.join("\n  ")
+    ))
+}
+
+/// Returns the module of `base_info`, linked with WASI and ready to be instantiated.
+/// It is compiled (or loaded from disk) only the first time in this process.
+///
+/// The module belongs to the engine given by `consume_fuel`, see [`engine`].
+/// If `wasi` is false, nothing is linked, so the module must not import anything.
+pub(crate) fn instance_pre(
+    base_info: &DllInfo,
+    consume_fuel: bool,
+    wasi: bool,
+) -> Result<InstancePre<WasmState>> {
+    let artifact = ArtifactKey::of(&base_info.path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", base_info.path.display(), e))?;
+    let key = ModuleKey {
+        artifact,
+        consume_fuel,
+        wasi,
+    };
+
+    if let Some(pre) = INSTANCE_PRES.lock().unwrap().get(&key) {
+        trace!("{}: using compiled module in memory", base_info.name);
+        return Ok(pre.clone());
+    }
+
+    let engine = engine(consume_fuel)?;
+    let module: Module = load_module(&engine, base_info, consume_fuel)?;
+
+    check_imports(&module, wasi).map_err(|e| anyhow!("{}: {}", base_info.name, e))?;
+
+    let mut linker = Linker::new(&engine);
+
+    if wasi {
+        // The WASI environment itself is set up per instance, see `WasiConfig`.
+        // Stores of WASI libraries always have a context.
+        preview1::add_to_linker_sync(&mut linker, |state: &mut WasmState| {
+            state
+                .wasi
+                .get_or_insert_with(|| WasiCtxBuilder::new().build_p1())
+        })?;
+    }
+    let pre = linker.instantiate_pre(&module)?;
+
+    INSTANCE_PRES.lock().unwrap().insert(key, pre.clone());
+
+    Ok(pre)
+}
+
+/// Fails with a list of the imports of `component` that cannot be satisfied,
+/// which are all imports but the WASI interfaces.
+fn check_component_imports(engine: &Engine, component: &Component) -> Result<()> {
+    let unsatisfied: Vec<_> = component
+        .component_type()
+        .imports(engine)
+        .map(|(name, _)| name)
+        .filter(|name| !name.starts_with("wasi:"))
+        .map(|name| format!("`{}`", name))
+        .collect();
+
+    if unsatisfied.is_empty() {
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "the component has imports that cannot be satisfied:\n  {}",
+        unsatisfied.join("\n  ")
+    ))
+}
+
+/// Returns the component of `base_info`, linked with WASI preview2 and ready to be instantiated.
+/// Like [`instance_pre`], it is compiled only the first time in this process.
+pub(crate) fn component_instance_pre(
+    base_info: &DllInfo,
+    consume_fuel: bool,
+) -> Result<component::InstancePre<WasmState>> {
+    let artifact = ArtifactKey::of(&base_info.path)
+        .map_err(|e| anyhow!("Failed to read {}: {}", base_info.path.display(), e))?;
+    let key = ModuleKey {
+        artifact,
+        consume_fuel,
+        wasi: true,
+    };
+
+    if let Some(pre) = COMPONENT_PRES.lock().unwrap().get(&key) {
+        trace!("{}: using compiled component in memory", base_info.name);
+        return Ok(pre.clone());
+    }
+
+    let engine = engine(consume_fuel)?;
+    let component: Component = load_module(&engine, base_info, consume_fuel)?;
+
+    check_component_imports(&engine, &component)
+        .map_err(|e| anyhow!("{}: {}", base_info.name, e))?;
+
+    let mut linker = component::Linker::new(&engine);
+    wasmtime_wasi::add_to_linker_sync(&mut linker)?;
+    let pre = linker.instantiate_pre(&component)?;
+
+    COMPONENT_PRES.lock().unwrap().insert(key, pre.clone());
+
+    Ok(pre)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_artifact_key_changes_on_replace() {
+        let path =
+            std::env::temp_dir().join(format!("dll-pack-artifact-key-{}.wasm", std::process::id()));
+
+        fs::write(&path, b"\0asm\x01\0\0\0").unwrap();
+        let old = ArtifactKey::of(&path).unwrap();
+        assert_eq!(old, ArtifactKey::of(&path).unwrap());
+
+        fs::write(&path, b"\0asm\x01\0\0\0\0").unwrap();
+        let new = ArtifactKey::of(&path).unwrap();
+        assert_ne!(old, new);
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_stale_module_cache() {
+        let engine = Engine::default();
+        let wasm_bin = b"\0asm\x01\0\0\0";
+        let module = Module::from_binary(&engine, wasm_bin).unwrap();
+
+        let path =
+            std::env::temp_dir().join(format!("dll-pack-module-cache-{}.bin", std::process::id()));
+        let key = module_key(&engine, Module::KIND, wasm_bin);
+        let mut cache_bin = format!("{}\n", key).into_bytes();
+        cache_bin.extend(module.serialize().unwrap());
+        fs::write(&path, cache_bin).unwrap();
+
+        assert!(read_cached_module::<Module>(&engine, &path, &key).is_some());
+
+        let other_key = module_key(&engine, Module::KIND, b"\0asm\x01\0\0\0\0");
+        assert_ne!(key, other_key);
+        assert!(read_cached_module::<Module>(&engine, &path, &other_key).is_none());
+
+        // A cached module is never read as a component.
+        let component_key = module_key(&engine, Component::KIND, wasm_bin);
+        assert_ne!(key, component_key);
+        assert!(read_cached_module::<Component>(&engine, &path, &component_key).is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_check_imports() {
+        // A module that imports a function `env::f: () -> ()`.
+        let wasm_bin: &[u8] = &[
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+            0x02, 0x09, 0x01, 0x03, b'e', b'n', b'v', 0x01, b'f', 0x00,
+            0x00, // import section
+        ];
+        let module = Module::from_binary(&Engine::default(), wasm_bin).unwrap();
+
+        let err = check_imports(&module, true).unwrap_err().to_string();
+        assert!(err.contains("function `env::f`"));
+        let err = check_imports(&module, false).unwrap_err().to_string();
+        assert!(err.contains("without WASI"));
+        assert!(err.contains("function `env::f`"));
+
+        // A module that imports a function `wasi_snapshot_preview1::f: () -> ()`.
+        let mut wasm_bin = vec![
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00, // header
+            0x01, 0x04, 0x01, 0x60, 0x00, 0x00, // type section: () -> ()
+            0x02, 0x1c, 0x01, 0x16, // import section
+        ];
+        wasm_bin.extend_from_slice(WASI_MODULE.as_bytes());
+        wasm_bin.extend_from_slice(&[0x01, b'f', 0x00, 0x00]);
+        let module = Module::from_binary(&Engine::default(), &wasm_bin).unwrap();
+
+        assert!(check_imports(&module, true).is_ok());
+        let err = check_imports(&module, false).unwrap_err().to_string();
+        assert!(err.contains("function `wasi_snapshot_preview1::f`"));
+    }
+}